@@ -0,0 +1,193 @@
+// Integer fields of 1 to 64 bits at arbitrary bit offsets in a string, with
+// bit 0 being the most significant bit of the first byte, as in redis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+impl FieldType {
+    fn min(&self) -> i128 {
+        if self.signed {
+            -(1i128 << (self.bits - 1))
+        } else {
+            0
+        }
+    }
+
+    fn max(&self) -> i128 {
+        if self.signed {
+            (1i128 << (self.bits - 1)) - 1
+        } else {
+            (1i128 << self.bits) - 1
+        }
+    }
+
+    // two's complement wrap-around into the field's range
+    fn wrap(&self, value: i128) -> i64 {
+        let mask = (1i128 << self.bits) - 1;
+        let mut value = value & mask;
+        if self.signed && value > self.max() {
+            value -= 1i128 << self.bits;
+        }
+        value as i64
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    #[default]
+    Wrap,
+    Sat,
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Get(FieldType, u64),
+    Set(FieldType, u64, i64, Overflow),
+    IncrBy(FieldType, u64, i64, Overflow),
+}
+
+impl Op {
+    pub fn is_write(&self) -> bool {
+        !matches!(self, Op::Get(..))
+    }
+}
+
+fn read(bytes: &[u8], offset: u64, field: FieldType) -> i64 {
+    let mut raw = 0u64;
+    for bit in offset..offset + field.bits as u64 {
+        let byte = bytes.get((bit / 8) as usize).copied().unwrap_or(0);
+        raw = (raw << 1) | ((byte >> (7 - bit % 8)) & 1) as u64;
+    }
+    field.wrap(raw as i128)
+}
+
+fn write(bytes: &mut Vec<u8>, offset: u64, field: FieldType, value: i64) {
+    let end = (offset + field.bits as u64).div_ceil(8) as usize;
+    if bytes.len() < end {
+        bytes.resize(end, 0);
+    }
+    for (i, bit) in (offset..offset + field.bits as u64).enumerate() {
+        let set = (value as u64 >> (field.bits as usize - 1 - i)) & 1 == 1;
+        let byte = &mut bytes[(bit / 8) as usize];
+        let mask = 1 << (7 - bit % 8);
+        if set {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+}
+
+fn handle_overflow(field: FieldType, value: i128, overflow: Overflow) -> Option<i64> {
+    if (field.min()..=field.max()).contains(&value) {
+        return Some(value as i64);
+    }
+    match overflow {
+        Overflow::Wrap => Some(field.wrap(value)),
+        Overflow::Sat => Some(value.clamp(field.min(), field.max()) as i64),
+        Overflow::Fail => None,
+    }
+}
+
+// GET and INCRBY reply with the field's (new) value and SET with the old one;
+// None is a write refused by OVERFLOW FAIL
+pub fn apply(bytes: &mut Vec<u8>, op: Op) -> Option<i64> {
+    match op {
+        Op::Get(field, offset) => Some(read(bytes, offset, field)),
+        Op::Set(field, offset, value, overflow) => {
+            let old = read(bytes, offset, field);
+            let value = handle_overflow(field, value as i128, overflow)?;
+            write(bytes, offset, field, value);
+            Some(old)
+        }
+        Op::IncrBy(field, offset, increment, overflow) => {
+            let old = read(bytes, offset, field);
+            let value = handle_overflow(field, old as i128 + increment as i128, overflow)?;
+            write(bytes, offset, field, value);
+            Some(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const U8: FieldType = FieldType {
+        signed: false,
+        bits: 8,
+    };
+    const I8: FieldType = FieldType {
+        signed: true,
+        bits: 8,
+    };
+
+    #[test]
+    fn test_set_then_get_u8() {
+        let mut bytes = Vec::new();
+        assert_eq!(
+            apply(&mut bytes, Op::Set(U8, 0, 200, Overflow::Wrap)),
+            Some(0)
+        );
+        assert_eq!(apply(&mut bytes, Op::Get(U8, 0)), Some(200));
+        assert_eq!(bytes, [200]);
+
+        // unaligned fields straddle bytes
+        apply(&mut bytes, Op::Set(U8, 4, 0xff, Overflow::Wrap));
+        assert_eq!(bytes, [0xcf, 0xf0]);
+        assert_eq!(apply(&mut bytes, Op::Get(U8, 4)), Some(0xff));
+        // reads past the end see zeros
+        assert_eq!(apply(&mut bytes, Op::Get(U8, 64)), Some(0));
+    }
+
+    #[test]
+    fn test_incrby_overflow_modes() {
+        let mut bytes = vec![250];
+        assert_eq!(
+            apply(&mut bytes, Op::IncrBy(U8, 0, 10, Overflow::Wrap)),
+            Some(4)
+        );
+        assert_eq!(
+            apply(&mut bytes, Op::IncrBy(U8, 0, -10, Overflow::Sat)),
+            Some(0)
+        );
+
+        let mut bytes = vec![127];
+        assert_eq!(
+            apply(&mut bytes, Op::IncrBy(I8, 0, 1, Overflow::Wrap)),
+            Some(-128)
+        );
+        assert_eq!(
+            apply(&mut bytes, Op::IncrBy(I8, 0, -1, Overflow::Fail)),
+            None
+        );
+        assert_eq!(bytes, [0x80]);
+        assert_eq!(
+            apply(&mut bytes, Op::Set(I8, 0, 1000, Overflow::Sat)),
+            Some(-128)
+        );
+        assert_eq!(apply(&mut bytes, Op::Get(I8, 0)), Some(127));
+    }
+
+    #[test]
+    fn test_wide_fields() {
+        let i64 = FieldType {
+            signed: true,
+            bits: 64,
+        };
+        let mut bytes = Vec::new();
+        apply(&mut bytes, Op::Set(i64, 3, i64::MIN, Overflow::Wrap));
+        assert_eq!(apply(&mut bytes, Op::Get(i64, 3)), Some(i64::MIN));
+        assert_eq!(
+            apply(&mut bytes, Op::IncrBy(i64, 3, -1, Overflow::Fail)),
+            None
+        );
+        assert_eq!(
+            apply(&mut bytes, Op::IncrBy(i64, 3, -1, Overflow::Wrap)),
+            Some(i64::MAX)
+        );
+    }
+}
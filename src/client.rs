@@ -0,0 +1,55 @@
+use crate::config::Config;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::RwLock;
+
+// every open connection, keyed by the client id handed out at accept time
+pub struct Clients {
+    next_id: AtomicU64,
+    clients: RwLock<BTreeMap<u64, SocketAddr>>,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Clients {
+            next_id: AtomicU64::new(1),
+            clients: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub async fn register(&self, addr: SocketAddr) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.clients.write().await.insert(id, addr);
+        id
+    }
+
+    pub async fn unregister(&self, id: u64) {
+        self.clients.write().await.remove(&id);
+    }
+
+    pub async fn list(&self) -> Vec<(u64, SocketAddr)> {
+        let clients = self.clients.read().await;
+        clients.iter().map(|(id, addr)| (*id, *addr)).collect()
+    }
+}
+
+// state owned by a single connection task
+pub struct Session {
+    pub authenticated: bool,
+    // CLIENT NO-TOUCH: reads don't update the keys' last access time
+    pub no_touch: bool,
+    // CLIENT NO-EVICT: recorded for when eviction exists, nothing reads it yet
+    #[allow(dead_code)]
+    pub no_evict: bool,
+}
+
+impl Session {
+    pub fn new(config: &Config) -> Self {
+        Session {
+            authenticated: config.requirepass.is_none(),
+            no_touch: false,
+            no_evict: false,
+        }
+    }
+}
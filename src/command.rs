@@ -0,0 +1,482 @@
+use crate::bitfield;
+use crate::store::Expiry;
+
+#[derive(Debug)]
+pub enum Command {
+    Ping,
+    Echo(Vec<u8>),
+    Set(String, Vec<u8>, SetOptions),
+    SetNx(String, Vec<u8>),
+    Get(String),
+    Append(String, Vec<u8>),
+    Strlen(String),
+    BitField(String, Vec<bitfield::Op>),
+    Incr(String),
+    Decr(String),
+    Del(Vec<String>),
+    Unlink(Vec<String>),
+    Exists(Vec<String>),
+    MGet(Vec<String>),
+    Type(String),
+    Ttl(String),
+    PTtl(String),
+    Persist(String),
+    Rename(String, String),
+    RenameNx(String, String),
+    Expire(String, Expiry, ExpireCondition),
+    PExpire(String, Expiry, ExpireCondition),
+    ExpireAt(String, Expiry, ExpireCondition),
+    PExpireAt(String, Expiry, ExpireCondition),
+    MSet(Vec<(String, Vec<u8>)>),
+    Keys(String),
+    DbSize,
+    FlushDb,
+    FlushAll,
+    Scan {
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+        type_filter: Option<String>,
+    },
+    Lcs(String, String, LcsOptions),
+    // the key and how many container elements to sample, 0 for all
+    MemoryUsage(String, usize),
+    ConfigGet(String),
+    // the requested sections, lowercased; empty means the default set
+    Info(Vec<String>),
+    Auth(Option<String>, String),
+    AclWhoami,
+    AclList,
+    ClientList,
+    ClientNoTouch(bool),
+    ClientNoEvict(bool),
+    ObjectIdletime(String),
+    Wait,
+    WaitAof(u64),
+    DebugExpireCycle,
+    DebugObject(String),
+    CmdInfo(Vec<String>),
+    CmdCount,
+    // EVAL, FUNCTION and friends, recognized only to be refused
+    Scripting,
+    Unknown,
+}
+
+// the EXPIRE family's NX/XX/GT/LT flags; a key without an expiry counts as
+// expiring never, so GT never applies to it and LT always does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExpireCondition {
+    #[default]
+    Always,
+    Nx,
+    Xx,
+    // XX GT is the same as GT alone
+    Gt,
+    Lt,
+    XxLt,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SetCondition {
+    #[default]
+    Always,
+    // NX: only set keys that don't exist
+    Nx,
+    // XX: only set keys that already exist
+    Xx,
+}
+
+#[derive(Debug, Default)]
+pub struct SetOptions {
+    pub expires_at: Option<Expiry>,
+    // KEEPTTL: an overwrite keeps the key's current expiry
+    pub keep_ttl: bool,
+    pub condition: SetCondition,
+    // GET: reply with the previous value instead of OK
+    pub get: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct LcsOptions {
+    pub len: bool,
+    pub idx: bool,
+    pub min_match_len: usize,
+    pub with_match_len: bool,
+}
+
+// static metadata reported by COMMAND INFO; key positions follow redis'
+// first/last/step convention, with a negative last counting from the end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    pub first_key: i64,
+    pub last_key: i64,
+    pub step: i64,
+}
+
+const fn spec(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [&'static str],
+    keys: (i64, i64, i64),
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key: keys.0,
+        last_key: keys.1,
+        step: keys.2,
+    }
+}
+
+const ADMIN: &[&str] = &["admin", "noscript", "loading", "stale"];
+
+pub const COMMAND_TABLE: &[CommandSpec] = &[
+    spec("acl", -2, ADMIN, (0, 0, 0)),
+    spec("append", 3, &["write", "denyoom", "fast"], (1, 1, 1)),
+    spec(
+        "auth",
+        -2,
+        &["noscript", "loading", "stale", "fast", "no_auth"],
+        (0, 0, 0),
+    ),
+    spec("bitfield", -2, &["write", "denyoom"], (1, 1, 1)),
+    spec("client", -2, ADMIN, (0, 0, 0)),
+    spec("command", -1, &["loading", "stale"], (0, 0, 0)),
+    spec("config", -2, ADMIN, (0, 0, 0)),
+    spec("dbsize", 1, &["readonly", "fast"], (0, 0, 0)),
+    spec("debug", -2, ADMIN, (0, 0, 0)),
+    spec("decr", 2, &["write", "denyoom", "fast"], (1, 1, 1)),
+    spec("del", -2, &["write"], (1, -1, 1)),
+    spec("echo", 2, &["fast"], (0, 0, 0)),
+    spec("exists", -2, &["readonly", "fast"], (1, -1, 1)),
+    spec("expire", -3, &["write", "fast"], (1, 1, 1)),
+    spec("expireat", -3, &["write", "fast"], (1, 1, 1)),
+    spec("flushall", -1, &["write"], (0, 0, 0)),
+    spec("flushdb", -1, &["write"], (0, 0, 0)),
+    spec("get", 2, &["readonly", "fast"], (1, 1, 1)),
+    spec("incr", 2, &["write", "denyoom", "fast"], (1, 1, 1)),
+    spec("info", -1, &["loading", "stale"], (0, 0, 0)),
+    spec("keys", 2, &["readonly"], (0, 0, 0)),
+    spec("lcs", -3, &["readonly"], (1, 2, 1)),
+    spec("memory", -2, &["readonly"], (0, 0, 0)),
+    spec("mget", -2, &["readonly", "fast"], (1, -1, 1)),
+    spec("mset", -3, &["write", "denyoom"], (1, -1, 2)),
+    spec("object", -2, &["readonly"], (2, 2, 1)),
+    spec("pexpire", -3, &["write", "fast"], (1, 1, 1)),
+    spec("pexpireat", -3, &["write", "fast"], (1, 1, 1)),
+    spec("persist", 2, &["write", "fast"], (1, 1, 1)),
+    spec("ping", -1, &["fast", "loading"], (0, 0, 0)),
+    spec("pttl", 2, &["readonly", "fast"], (1, 1, 1)),
+    spec("rename", 3, &["write"], (1, 2, 1)),
+    spec("renamenx", 3, &["write", "fast"], (1, 2, 1)),
+    spec("scan", -2, &["readonly"], (0, 0, 0)),
+    spec("set", -3, &["write", "denyoom"], (1, 1, 1)),
+    spec("setnx", 3, &["write", "denyoom", "fast"], (1, 1, 1)),
+    spec("strlen", 2, &["readonly", "fast"], (1, 1, 1)),
+    spec("ttl", 2, &["readonly", "fast"], (1, 1, 1)),
+    spec("type", 2, &["readonly", "fast"], (1, 1, 1)),
+    spec("unlink", -2, &["write", "fast"], (1, -1, 1)),
+    spec("wait", 3, &["noscript"], (0, 0, 0)),
+    spec("waitaof", 4, &["noscript"], (0, 0, 0)),
+];
+
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMAND_TABLE
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandKind {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Command {
+    // single source of truth for what a command does to the keyspace; anything
+    // that needs to know about write commands (the maxmemory check today) should
+    // ask here instead of matching on its own
+    pub fn kind(&self) -> CommandKind {
+        match self {
+            Command::Ping => CommandKind::Read,
+            Command::Echo(_) => CommandKind::Read,
+            Command::Set(..) => CommandKind::Write,
+            Command::SetNx(..) => CommandKind::Write,
+            Command::Get(_) => CommandKind::Read,
+            Command::Append(..) => CommandKind::Write,
+            Command::Strlen(_) => CommandKind::Read,
+            Command::BitField(..) => CommandKind::Write,
+            Command::Incr(_) => CommandKind::Write,
+            Command::Decr(_) => CommandKind::Write,
+            Command::Del(_) | Command::Unlink(_) => CommandKind::Write,
+            Command::Exists(_) => CommandKind::Read,
+            Command::MGet(_) => CommandKind::Read,
+            Command::Type(_) => CommandKind::Read,
+            Command::Ttl(_) | Command::PTtl(_) => CommandKind::Read,
+            Command::Persist(_) => CommandKind::Write,
+            Command::Rename(..) | Command::RenameNx(..) => CommandKind::Write,
+            Command::Expire(..) | Command::PExpire(..) => CommandKind::Write,
+            Command::ExpireAt(..) | Command::PExpireAt(..) => CommandKind::Write,
+            Command::MSet(_) => CommandKind::Write,
+            Command::Keys(_) => CommandKind::Read,
+            Command::DbSize => CommandKind::Read,
+            Command::FlushDb | Command::FlushAll => CommandKind::Write,
+            Command::Scan { .. } => CommandKind::Read,
+            Command::Lcs(..) => CommandKind::Read,
+            Command::MemoryUsage(..) => CommandKind::Read,
+            Command::ConfigGet(_) => CommandKind::Admin,
+            Command::Info(_) => CommandKind::Admin,
+            Command::Auth(..) => CommandKind::Admin,
+            Command::AclWhoami => CommandKind::Admin,
+            Command::AclList => CommandKind::Admin,
+            Command::ClientList => CommandKind::Admin,
+            Command::ClientNoTouch(_) => CommandKind::Admin,
+            Command::ClientNoEvict(_) => CommandKind::Admin,
+            Command::ObjectIdletime(_) => CommandKind::Read,
+            Command::Wait => CommandKind::Admin,
+            Command::WaitAof(..) => CommandKind::Admin,
+            Command::DebugExpireCycle => CommandKind::Admin,
+            Command::DebugObject(_) => CommandKind::Admin,
+            Command::CmdInfo(_) => CommandKind::Admin,
+            Command::CmdCount => CommandKind::Admin,
+            Command::Scripting => CommandKind::Admin,
+            Command::Unknown => CommandKind::Admin,
+        }
+    }
+
+    // the COMMAND_TABLE entry this command is dispatched under; None for
+    // commands we only recognize in order to refuse them
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match self {
+            Command::Ping => "ping",
+            Command::Echo(_) => "echo",
+            Command::Set(..) => "set",
+            Command::SetNx(..) => "setnx",
+            Command::Get(_) => "get",
+            Command::Append(..) => "append",
+            Command::Strlen(_) => "strlen",
+            Command::BitField(..) => "bitfield",
+            Command::Incr(_) => "incr",
+            Command::Decr(_) => "decr",
+            Command::Del(_) => "del",
+            Command::Unlink(_) => "unlink",
+            Command::Exists(_) => "exists",
+            Command::MGet(_) => "mget",
+            Command::Type(_) => "type",
+            Command::Ttl(_) => "ttl",
+            Command::PTtl(_) => "pttl",
+            Command::Persist(_) => "persist",
+            Command::Rename(..) => "rename",
+            Command::RenameNx(..) => "renamenx",
+            Command::Expire(..) => "expire",
+            Command::PExpire(..) => "pexpire",
+            Command::ExpireAt(..) => "expireat",
+            Command::PExpireAt(..) => "pexpireat",
+            Command::MSet(_) => "mset",
+            Command::Keys(_) => "keys",
+            Command::DbSize => "dbsize",
+            Command::FlushDb => "flushdb",
+            Command::FlushAll => "flushall",
+            Command::Scan { .. } => "scan",
+            Command::Lcs(..) => "lcs",
+            Command::MemoryUsage(..) => "memory",
+            Command::ConfigGet(_) => "config",
+            Command::Info(_) => "info",
+            Command::Auth(..) => "auth",
+            Command::AclWhoami | Command::AclList => "acl",
+            Command::ClientList | Command::ClientNoTouch(_) | Command::ClientNoEvict(_) => "client",
+            Command::ObjectIdletime(_) => "object",
+            Command::Wait => "wait",
+            Command::WaitAof(..) => "waitaof",
+            Command::DebugExpireCycle | Command::DebugObject(_) => "debug",
+            Command::CmdInfo(_) | Command::CmdCount => "command",
+            Command::Scripting | Command::Unknown => return None,
+        };
+        Some(name)
+    }
+
+    pub fn is_debug(&self) -> bool {
+        matches!(self, Command::DebugExpireCycle | Command::DebugObject(_))
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.kind() == CommandKind::Write
+    }
+
+    // writes that can grow the dataset, refused once maxmemory is reached;
+    // DEL is a write too but only ever frees memory
+    pub fn is_denyoom(&self) -> bool {
+        self.is_write() && self.has_flag("denyoom")
+    }
+
+    // the few commands still served while the dataset is loading
+    pub fn allowed_while_loading(&self) -> bool {
+        self.has_flag("loading")
+    }
+
+    fn has_flag(&self, flag: &str) -> bool {
+        self.name()
+            .and_then(lookup)
+            .is_some_and(|spec| spec.flags.contains(&flag))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn one_of_each() -> Vec<Command> {
+        vec![
+            Command::Ping,
+            Command::Echo(b"foo".to_vec()),
+            Command::Set("foo".to_string(), b"bar".to_vec(), SetOptions::default()),
+            Command::SetNx("foo".to_string(), b"bar".to_vec()),
+            Command::Get("foo".to_string()),
+            Command::Append("foo".to_string(), b"bar".to_vec()),
+            Command::Strlen("foo".to_string()),
+            Command::BitField("foo".to_string(), Vec::new()),
+            Command::Incr("foo".to_string()),
+            Command::Decr("foo".to_string()),
+            Command::Del(vec!["foo".to_string()]),
+            Command::Unlink(vec!["foo".to_string()]),
+            Command::Exists(vec!["foo".to_string()]),
+            Command::MGet(vec!["foo".to_string()]),
+            Command::Type("foo".to_string()),
+            Command::Ttl("foo".to_string()),
+            Command::PTtl("foo".to_string()),
+            Command::Persist("foo".to_string()),
+            Command::Rename("foo".to_string(), "bar".to_string()),
+            Command::RenameNx("foo".to_string(), "bar".to_string()),
+            Command::Expire(
+                "foo".to_string(),
+                Expiry::At(std::time::UNIX_EPOCH),
+                ExpireCondition::Gt,
+            ),
+            Command::PExpire(
+                "foo".to_string(),
+                Expiry::At(std::time::UNIX_EPOCH),
+                ExpireCondition::Gt,
+            ),
+            Command::ExpireAt(
+                "foo".to_string(),
+                Expiry::At(std::time::UNIX_EPOCH),
+                ExpireCondition::Gt,
+            ),
+            Command::PExpireAt(
+                "foo".to_string(),
+                Expiry::At(std::time::UNIX_EPOCH),
+                ExpireCondition::Gt,
+            ),
+            Command::MSet(vec![("foo".to_string(), b"bar".to_vec())]),
+            Command::Keys("*".to_string()),
+            Command::DbSize,
+            Command::FlushDb,
+            Command::FlushAll,
+            Command::Scan {
+                cursor: 0,
+                pattern: None,
+                count: 10,
+                type_filter: None,
+            },
+            Command::Lcs("a".to_string(), "b".to_string(), LcsOptions::default()),
+            Command::MemoryUsage("foo".to_string(), 5),
+            Command::ConfigGet("dir".to_string()),
+            Command::Info(Vec::new()),
+            Command::Auth(None, "secret".to_string()),
+            Command::AclWhoami,
+            Command::AclList,
+            Command::ClientList,
+            Command::ClientNoTouch(true),
+            Command::ClientNoEvict(false),
+            Command::ObjectIdletime("foo".to_string()),
+            Command::Wait,
+            Command::WaitAof(1),
+            Command::DebugExpireCycle,
+            Command::DebugObject("foo".to_string()),
+            Command::CmdInfo(vec!["get".to_string()]),
+            Command::CmdCount,
+            Command::Scripting,
+            Command::Unknown,
+        ]
+    }
+
+    #[test]
+    fn test_denyoom_flag() {
+        let set = Command::Set("k".to_string(), b"v".to_vec(), SetOptions::default());
+        assert!(set.is_denyoom());
+        assert!(!Command::Get("k".to_string()).is_denyoom());
+        assert!(!Command::Del(vec!["k".to_string()]).is_denyoom());
+        assert!(!Command::Unknown.is_denyoom());
+    }
+
+    #[test]
+    fn test_allowed_while_loading() {
+        assert!(Command::Ping.allowed_while_loading());
+        assert!(Command::ConfigGet("port".to_string()).allowed_while_loading());
+        assert!(Command::Auth(None, "pw".to_string()).allowed_while_loading());
+        assert!(Command::Info(Vec::new()).allowed_while_loading());
+        assert!(!Command::Get("k".to_string()).allowed_while_loading());
+    }
+
+    // kind() and the table's write/readonly flags are kept by hand, so check
+    // they agree for every command
+    #[test]
+    fn test_kind_matches_table_flags() {
+        for command in one_of_each() {
+            let Some(spec) = command.name().and_then(lookup) else {
+                assert_eq!(command.kind(), CommandKind::Admin, "{:?}", command);
+                continue;
+            };
+            let write = spec.flags.contains(&"write");
+            assert_eq!(command.is_write(), write, "{:?}", command);
+            if spec.flags.contains(&"readonly") {
+                assert_eq!(command.kind(), CommandKind::Read, "{:?}", command);
+            }
+            if spec.flags.contains(&"admin") {
+                assert_eq!(command.kind(), CommandKind::Admin, "{:?}", command);
+            }
+        }
+        assert!(Command::Del(Vec::new()).is_write());
+        assert!(Command::FlushAll.is_write());
+        assert_eq!(Command::Keys("*".to_string()).kind(), CommandKind::Read);
+        assert_eq!(Command::ClientList.kind(), CommandKind::Admin);
+    }
+
+    #[test]
+    fn test_set_is_write_get_is_read() {
+        let set = Command::Set("foo".to_string(), b"bar".to_vec(), SetOptions::default());
+        assert_eq!(set.kind(), CommandKind::Write);
+        assert!(set.is_write());
+
+        let get = Command::Get("foo".to_string());
+        assert_eq!(get.kind(), CommandKind::Read);
+        assert!(!get.is_write());
+    }
+
+    #[test]
+    fn test_command_key_specs() {
+        let keys = |name| lookup(name).map(|s| (s.first_key, s.last_key, s.step));
+        assert_eq!(keys("set"), Some((1, 1, 1)));
+        assert_eq!(keys("DEL"), Some((1, -1, 1)));
+        assert_eq!(keys("MSET"), Some((1, -1, 2)));
+        assert_eq!(keys("ping"), Some((0, 0, 0)));
+        assert_eq!(keys("nope"), None);
+    }
+
+    // the table lists exactly the commands that dispatch, so COMMAND COUNT
+    // and COMMAND INFO can't drift from what the server runs
+    #[test]
+    fn test_command_table_matches_dispatch() {
+        let mut dispatched: Vec<_> = one_of_each().iter().filter_map(|c| c.name()).collect();
+        dispatched.sort();
+        dispatched.dedup();
+        let mut table: Vec<_> = COMMAND_TABLE.iter().map(|spec| spec.name).collect();
+        table.sort();
+        assert_eq!(dispatched, table);
+    }
+}
@@ -1,40 +1,266 @@
+use crate::parse::Limits;
 use std::env::args;
+use std::net::IpAddr;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
-    dir: Option<String>,
-    dbfilename: Option<String>,
+    pub bind: String,
+    pub port: u16,
+    pub dir: Option<String>,
+    pub dbfilename: Option<String>,
+    pub proto_max_bulk_len: usize,
+    pub proto_max_multibulk_len: usize,
+    pub protected_mode: bool,
+    pub requirepass: Option<String>,
+    pub enable_debug_command: bool,
+    // bytes, 0 for no limit; nothing is ever evicted, so going over it
+    // behaves like maxmemory-policy noeviction
+    pub maxmemory: usize,
+    // seconds a connection may idle before keepalive probes start, 0 for off
+    pub tcp_keepalive: u64,
 }
 
-impl Config {
-    pub fn new() -> Self {
+impl Default for Config {
+    fn default() -> Self {
         Config {
+            bind: "127.0.0.1".to_string(),
+            port: 6379,
             dir: None,
             dbfilename: None,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            proto_max_multibulk_len: 1024 * 1024,
+            protected_mode: true,
+            requirepass: None,
+            enable_debug_command: false,
+            maxmemory: 0,
+            tcp_keepalive: 300,
         }
     }
+}
 
-    pub fn from_args(&mut self) {
-        let args: Vec<String> = args().collect();
-        let mut iter = args.iter();
-        while let Some(arg) = iter.next() {
-            match arg.to_lowercase().as_str() {
-                "--dir" => {
-                    self.dir = iter.next().map(|s| s.to_owned());
+fn parse_bool(flag: &str, value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("invalid value '{}' for '{}'", value, flag)),
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Result<Self, String> {
+        Config::from_iter(args().skip(1))
+    }
+
+    pub fn from_iter(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut config = Config::default();
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let flag = arg.to_lowercase();
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for '{}'", arg))
+            };
+            match flag.as_str() {
+                "--bind" => config.bind = value()?,
+                "--port" => {
+                    let port = value()?;
+                    config.port = port
+                        .parse()
+                        .map_err(|_| format!("invalid port '{}'", port))?;
+                }
+                "--dir" => config.dir = Some(value()?),
+                "--dbfilename" => config.dbfilename = Some(value()?),
+                "--protected-mode" => config.protected_mode = parse_bool(&flag, &value()?)?,
+                "--enable-debug-command" => {
+                    config.enable_debug_command = parse_bool(&flag, &value()?)?
+                }
+                "--requirepass" => config.requirepass = Some(value()?),
+                "--proto-max-bulk-len" => {
+                    let len = value()?;
+                    config.proto_max_bulk_len = len
+                        .parse()
+                        .map_err(|_| format!("invalid proto-max-bulk-len '{}'", len))?;
                 }
-                "--dbfilename" => {
-                    self.dbfilename = iter.next().map(|s| s.to_owned());
+                "--proto-max-multibulk-len" => {
+                    let len = value()?;
+                    config.proto_max_multibulk_len = len
+                        .parse()
+                        .map_err(|_| format!("invalid proto-max-multibulk-len '{}'", len))?;
                 }
-                _ => {}
+                "--maxmemory" => {
+                    let bytes = value()?;
+                    config.maxmemory = bytes
+                        .parse()
+                        .map_err(|_| format!("invalid maxmemory '{}'", bytes))?;
+                }
+                "--tcp-keepalive" => {
+                    let secs = value()?;
+                    config.tcp_keepalive = secs
+                        .parse()
+                        .map_err(|_| format!("invalid tcp-keepalive '{}'", secs))?;
+                }
+                _ => return Err(format!("unknown option '{}'", arg)),
             }
         }
+        Ok(config)
     }
 
     pub fn get(&self, key: &str) -> Option<String> {
         match key.to_lowercase().as_str() {
+            "bind" => Some(self.bind.clone()),
+            "port" => Some(self.port.to_string()),
             "dir" => self.dir.clone(),
             "dbfilename" => self.dbfilename.clone(),
+            "protected-mode" => Some(if self.protected_mode { "yes" } else { "no" }.to_string()),
+            "enable-debug-command" => Some(
+                if self.enable_debug_command {
+                    "yes"
+                } else {
+                    "no"
+                }
+                .to_string(),
+            ),
+            "requirepass" => Some(self.requirepass.clone().unwrap_or_default()),
+            "proto-max-bulk-len" => Some(self.proto_max_bulk_len.to_string()),
+            "proto-max-multibulk-len" => Some(self.proto_max_multibulk_len.to_string()),
+            "maxmemory" => Some(self.maxmemory.to_string()),
+            "tcp-keepalive" => Some(self.tcp_keepalive.to_string()),
             _ => None,
         }
     }
-}
\ No newline at end of file
+
+    // protected mode only kicks in when the server is reachable from outside
+    // and no password guards it
+    pub fn denies_peer(&self, peer: IpAddr) -> bool {
+        let bind_is_loopback = self.bind == "localhost"
+            || self
+                .bind
+                .parse::<IpAddr>()
+                .is_ok_and(|bind| bind.is_loopback());
+        self.protected_mode
+            && self.requirepass.is_none()
+            && !bind_is_loopback
+            && !peer.is_loopback()
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_bulk_len: self.proto_max_bulk_len,
+            max_multibulk_len: self.proto_max_multibulk_len,
+        }
+    }
+
+    pub fn get_file_path(&self) -> Option<String> {
+        match (&self.dir, &self.dbfilename) {
+            (Some(dir), Some(dbfilename)) => Some(format!("{}/{}", dir, dbfilename)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        Config::from_iter(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_from_iter_full_args() {
+        let config = parse(&[
+            "--bind",
+            "0.0.0.0",
+            "--port",
+            "6380",
+            "--dir",
+            "/tmp/redis",
+            "--dbfilename",
+            "dump.rdb",
+        ])
+        .unwrap();
+        assert_eq!(config.bind, "0.0.0.0");
+        assert_eq!(config.port, 6380);
+        assert_eq!(
+            config.get_file_path().as_deref(),
+            Some("/tmp/redis/dump.rdb")
+        );
+    }
+
+    #[test]
+    fn test_from_iter_defaults() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.bind, "127.0.0.1");
+        assert_eq!(config.port, 6379);
+        assert_eq!(config.get_file_path(), None);
+    }
+
+    #[test]
+    fn test_from_iter_rejects_bad_port() {
+        assert!(parse(&["--port", "abc"]).is_err());
+        assert!(parse(&["--port", "70000"]).is_err());
+        assert!(parse(&["--port"]).is_err());
+    }
+
+    #[test]
+    fn test_from_iter_maxmemory() {
+        assert_eq!(parse(&[]).unwrap().maxmemory, 0);
+        let config = parse(&["--maxmemory", "1048576"]).unwrap();
+        assert_eq!(config.get("maxmemory").as_deref(), Some("1048576"));
+        assert!(parse(&["--maxmemory", "1mb"]).is_err());
+    }
+
+    #[test]
+    fn test_from_iter_proto_max_multibulk_len() {
+        assert_eq!(parse(&[]).unwrap().proto_max_multibulk_len, 1024 * 1024);
+        let config = parse(&["--proto-max-multibulk-len", "16"]).unwrap();
+        assert_eq!(config.proto_max_multibulk_len, 16);
+        assert_eq!(config.get("proto-max-multibulk-len").as_deref(), Some("16"));
+        assert_eq!(config.limits().max_multibulk_len, 16);
+        assert!(parse(&["--proto-max-multibulk-len", "-1"]).is_err());
+        assert!(parse(&["--proto-max-multibulk-len"]).is_err());
+    }
+
+    #[test]
+    fn test_from_iter_tcp_keepalive() {
+        assert_eq!(parse(&[]).unwrap().tcp_keepalive, 300);
+        let config = parse(&["--tcp-keepalive", "0"]).unwrap();
+        assert_eq!(config.tcp_keepalive, 0);
+        assert_eq!(config.get("tcp-keepalive").as_deref(), Some("0"));
+        assert!(parse(&["--tcp-keepalive", "-1"]).is_err());
+        assert!(parse(&["--tcp-keepalive"]).is_err());
+    }
+
+    #[test]
+    fn test_from_iter_rejects_unknown_flag() {
+        assert!(parse(&["--nope", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_denies_peer() {
+        let loopback: IpAddr = "127.0.0.1".parse().unwrap();
+        let remote: IpAddr = "10.0.0.5".parse().unwrap();
+
+        let config = parse(&["--bind", "0.0.0.0"]).unwrap();
+        assert!(config.denies_peer(remote));
+        assert!(!config.denies_peer(loopback));
+
+        let config = parse(&["--bind", "0.0.0.0", "--protected-mode", "no"]).unwrap();
+        assert!(!config.denies_peer(remote));
+
+        let config = parse(&["--bind", "0.0.0.0", "--requirepass", "secret"]).unwrap();
+        assert!(!config.denies_peer(remote));
+
+        // bound to loopback only, so remote peers can't reach us anyway
+        let config = Config::default();
+        assert!(!config.denies_peer(remote));
+    }
+
+    #[test]
+    fn test_from_iter_yes_no_flags() {
+        let config = parse(&["--enable-debug-command", "yes", "--protected-mode", "NO"]).unwrap();
+        assert!(config.enable_debug_command);
+        assert!(!config.protected_mode);
+        assert!(parse(&["--enable-debug-command", "maybe"]).is_err());
+    }
+}
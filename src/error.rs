@@ -0,0 +1,65 @@
+use thiserror::Error;
+
+// Errors raised while running a command. Everything except `Io` is reported
+// back to the client as an error reply and the connection stays open.
+#[derive(Debug, Error)]
+pub enum RespError {
+    #[error("ERR unknown command")]
+    UnknownCommand,
+    #[error("DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. In this mode connections are only accepted from the loopback interface. If you want to connect from external computers to Redis you may restart it with the '--protected-mode no' option, or bind it to the loopback interface only.")]
+    Denied,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR no such key")]
+    NoSuchKey,
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+    #[error("ERR invalid expire time in '{0}' command")]
+    InvalidExpireTime(&'static str),
+    #[error("ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.")]
+    BitfieldType,
+    #[error("ERR bit offset is not an integer or out of range")]
+    BitOffset,
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(&'static str),
+    #[error("ERR NX and XX, GT or LT options at the same time are not compatible")]
+    ExpireNxConflict,
+    #[error("ERR GT and LT options at the same time are not compatible")]
+    ExpireGtLtConflict,
+    #[error("ERR invalid cursor")]
+    InvalidCursor,
+    #[error("ERR syntax error")]
+    Syntax,
+    // keys are stored as text, so a non-UTF-8 one would collide with others
+    #[error("ERR keys and text arguments must be valid UTF-8")]
+    NotUtf8,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR If you want both the length and indexes, please just use IDX.")]
+    LcsLenAndIdx,
+    #[error("ERR DEBUG command not allowed. Restart the server with '--enable-debug-command yes' to enable it.")]
+    DebugNotAllowed,
+    #[error("NOAUTH Authentication required.")]
+    NoAuth,
+    #[error("WRONGPASS invalid username-password pair or user is disabled.")]
+    WrongPass,
+    #[error("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?")]
+    AuthNotConfigured,
+    #[error("ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.")]
+    AofDisabled,
+    #[error("ERR This Redis build does not support scripting")]
+    NoScripting,
+    #[error("LOADING Redis is loading the dataset in memory")]
+    Loading,
+    #[error("OOM command not allowed when used memory > 'maxmemory'.")]
+    OutOfMemory,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl RespError {
+    #[cfg(test)]
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, RespError::Io(e) if crate::parse::is_incomplete(e))
+    }
+}
@@ -0,0 +1,115 @@
+// A run of equal bytes found while walking back through the LCS table,
+// as inclusive ranges into each input.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Match {
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+}
+
+impl Match {
+    pub fn len(&self) -> usize {
+        self.a.1 - self.a.0 + 1
+    }
+}
+
+pub struct Lcs {
+    pub subsequence: Vec<u8>,
+    // from the end of the inputs towards the start, like redis reports them
+    pub matches: Vec<Match>,
+}
+
+pub fn lcs(a: &[u8], b: &[u8]) -> Lcs {
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut subsequence = Vec::with_capacity(table[a.len() * width + b.len()] as usize);
+    let mut matches = Vec::new();
+    let mut current: Option<Match> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            subsequence.push(a[i - 1]);
+            match &mut current {
+                // contiguous with the run we are building, extend it backwards
+                Some(run) if run.a.0 == i && run.b.0 == j => {
+                    run.a.0 -= 1;
+                    run.b.0 -= 1;
+                }
+                Some(_) => emit = true,
+                None => {
+                    current = Some(Match {
+                        a: (i - 1, i - 1),
+                        b: (j - 1, j - 1),
+                    })
+                }
+            }
+            if current
+                .as_ref()
+                .is_some_and(|run| run.a.0 == 0 || run.b.0 == 0)
+            {
+                emit = true;
+            }
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit = current.is_some();
+        }
+
+        if emit {
+            matches.extend(current.take());
+        }
+    }
+
+    subsequence.reverse();
+    Lcs {
+        subsequence,
+        matches,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lcs() {
+        let result = lcs(b"ohmytext", b"mynewtext");
+        assert_eq!(result.subsequence, b"mytext");
+        assert_eq!(
+            result.matches,
+            vec![
+                Match {
+                    a: (4, 7),
+                    b: (5, 8)
+                },
+                Match {
+                    a: (2, 3),
+                    b: (0, 1)
+                },
+            ]
+        );
+        assert_eq!(result.matches[0].len(), 4);
+    }
+
+    #[test]
+    fn test_lcs_empty() {
+        let result = lcs(b"", b"abc");
+        assert!(result.subsequence.is_empty());
+        assert!(result.matches.is_empty());
+    }
+}
@@ -1,52 +1,156 @@
+mod bitfield;
+mod client;
+mod command;
+mod config;
+mod error;
+mod glob;
+mod lcs;
 mod parse;
+mod resp;
 mod store;
+use client::Session;
+use command::{Command, SetCondition, SetOptions};
+use config::Config;
+use error::RespError;
 use std::io::Error;
-use store::Database;
+use store::{Database, SetOutcome};
 
-use parse::parse_command;
+use parse::{parse_frame, FrameScan};
+use socket2::{SockRef, TcpKeepalive};
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
     spawn,
 };
 
-pub enum Command {
-    Ping,
-    Echo(String),
-    Set(String, String, Option<u64>),
-    Get(String),
-    Keys(String),
-    ConfigGet(String),
-    Unknown,
+// with GET, SET replies with the old value (nil if there was none) whether or
+// not it wrote; without it, +OK when it wrote and nil when NX/XX held it back
+fn set_reply(outcome: SetOutcome, get: bool) -> Vec<u8> {
+    match (get, outcome.written) {
+        (true, _) => resp::encode_bulk_string(outcome.old.as_deref()),
+        (false, true) => b"+OK\r\n".to_vec(),
+        (false, false) => b"$-1\r\n".to_vec(),
+    }
+}
+
+// INFO's memory fields, from the store's running estimate
+async fn info_memory(db: &Database) -> String {
+    format!(
+        "# Memory\r\nused_memory:{}\r\nmaxmemory:{}\r\n",
+        db.used_memory().await,
+        db.config().maxmemory
+    )
+}
+
+// INFO's persistence fields; there is no SAVE, BGSAVE or AOF, so the last
+// save is startup and the background save status is always ok
+fn info_persistence(db: &Database) -> String {
+    format!(
+        "# Persistence\r\nloading:{}\r\nrdb_changes_since_last_save:{}\r\nrdb_last_save_time:{}\r\nrdb_last_bgsave_status:ok\r\naof_enabled:0\r\n",
+        db.is_loading() as u8,
+        db.dirty(),
+        db.last_save()
+    )
 }
 
+// runs a command and returns its encoded reply; writing it is up to the caller
 async fn execute_command(
-    stream: &mut TcpStream,
+    session: &mut Session,
     command: Command,
     db: &Database,
-) -> Result<(), Error> {
+) -> Result<Vec<u8>, RespError> {
+    if !session.authenticated && !matches!(command, Command::Auth(..)) {
+        return Err(RespError::NoAuth);
+    }
+
+    if db.is_loading() && !command.allowed_while_loading() {
+        return Err(RespError::Loading);
+    }
+
+    if command.is_debug() && !db.config().enable_debug_command {
+        return Err(RespError::DebugNotAllowed);
+    }
+
+    if command.is_denyoom() && db.over_maxmemory().await {
+        return Err(RespError::OutOfMemory);
+    }
+
     let resp: String = match command {
         Command::Ping => "+PONG\r\n".to_string(),
         Command::Echo(echo_arg) => {
-            format!("+{}\r\n", echo_arg)
+            return Ok(resp::encode_bulk_string(Some(&echo_arg)));
         }
-        Command::Set(key, value, expiry_in_ms) => match expiry_in_ms {
-            Some(expiry_in_ms) => {
-                db.set_with_expire(&key, &value, expiry_in_ms).await;
-                "+OK\r\n".to_string()
-            }
-            None => {
-                db.set(&key, &value).await;
-                "+OK\r\n".to_string()
+        Command::Set(key, value, options) => {
+            let get = options.get;
+            let outcome = db.set_owned(key, value, options).await?;
+            return Ok(set_reply(outcome, get));
+        }
+        Command::SetNx(key, value) => {
+            let options = SetOptions {
+                condition: SetCondition::Nx,
+                ..SetOptions::default()
+            };
+            let outcome = db.set_owned(key, value, options).await?;
+            format!(":{}\r\n", outcome.written as u8)
+        }
+        Command::Get(key) => {
+            let value = db.get_with(&key, !session.no_touch).await?;
+            return Ok(resp::encode_bulk_string(value.as_deref()));
+        }
+        Command::Ttl(key) => format!(":{}\r\n", db.ttl(&key).await.reply(false)),
+        Command::PTtl(key) => format!(":{}\r\n", db.ttl(&key).await.reply(true)),
+        Command::Expire(key, expires_at, condition)
+        | Command::PExpire(key, expires_at, condition)
+        | Command::ExpireAt(key, expires_at, condition)
+        | Command::PExpireAt(key, expires_at, condition) => {
+            let applied = db.set_expiry(&key, expires_at, condition).await;
+            format!(":{}\r\n", applied as u8)
+        }
+        Command::Rename(src, dst) => {
+            db.rename(&src, &dst, false).await?;
+            "+OK\r\n".to_string()
+        }
+        Command::RenameNx(src, dst) => {
+            format!(":{}\r\n", db.rename(&src, &dst, true).await? as u8)
+        }
+        Command::Persist(key) => format!(":{}\r\n", db.persist(&key).await as u8),
+        Command::Type(key) => format!("+{}\r\n", db.key_type(&key).await),
+        Command::MSet(pairs) => {
+            db.mset(pairs).await;
+            "+OK\r\n".to_string()
+        }
+        Command::Append(key, value) => {
+            format!(":{}\r\n", db.append(&key, &value).await?)
+        }
+        Command::BitField(key, ops) => {
+            let results = db.bitfield(&key, &ops).await?;
+            let mut resp = format!("*{}\r\n", results.len());
+            for result in results {
+                match result {
+                    Some(n) => resp.push_str(&format!(":{}\r\n", n)),
+                    None => resp.push_str("$-1\r\n"),
+                }
             }
-        },
-        Command::Get(key) => match db.get(&key).await {
-            Some(value) => {
-                format!("+{}\r\n", value)
+            resp
+        }
+        Command::Strlen(key) => format!(":{}\r\n", db.strlen(&key).await?),
+        Command::Incr(key) => format!(":{}\r\n", db.incr_by(&key, 1).await?),
+        Command::Decr(key) => format!(":{}\r\n", db.incr_by(&key, -1).await?),
+        // values are dropped inline either way, so UNLINK is just DEL
+        Command::Del(keys) | Command::Unlink(keys) => format!(":{}\r\n", db.del(&keys).await),
+        Command::Exists(keys) => format!(":{}\r\n", db.exists(&keys).await),
+        Command::MGet(keys) => {
+            let values = db.mget(&keys, !session.no_touch).await;
+            let mut resp = format!("*{}\r\n", values.len()).into_bytes();
+            for value in values {
+                resp.extend(resp::encode_bulk_string(value.as_deref()));
             }
-            None => "$-1\r\n".to_string(),
-        },
+            return Ok(resp);
+        }
         Command::Keys(pattern) => {
             let mut keys = db.keys(&pattern).await;
             keys.sort();
@@ -57,6 +161,63 @@ async fn execute_command(
             }
             resp
         }
+        Command::DbSize => format!(":{}\r\n", db.dbsize().await),
+        Command::FlushDb | Command::FlushAll => {
+            db.flush().await;
+            "+OK\r\n".to_string()
+        }
+        Command::Scan {
+            cursor,
+            pattern,
+            count,
+            type_filter,
+        } => {
+            let (next, keys) = db
+                .scan(cursor, count, pattern.as_deref(), type_filter.as_deref())
+                .await;
+            let next = next.to_string();
+            let mut resp = format!("*2\r\n${}\r\n{}\r\n*{}\r\n", next.len(), next, keys.len());
+            for key in keys {
+                resp.push_str(&format!("${}\r\n{}\r\n", key.len(), key));
+            }
+            resp
+        }
+        Command::Lcs(key1, key2, options) => {
+            let a = db.get_with(&key1, !session.no_touch).await?;
+            let b = db.get_with(&key2, !session.no_touch).await?;
+            let (a, b) = (a.unwrap_or_default(), b.unwrap_or_default());
+            let result = lcs::lcs(&a, &b);
+            if options.len {
+                format!(":{}\r\n", result.subsequence.len())
+            } else if options.idx {
+                let matches: Vec<_> = result
+                    .matches
+                    .iter()
+                    .filter(|m| m.len() >= options.min_match_len)
+                    .collect();
+                let mut resp = format!("*4\r\n$7\r\nmatches\r\n*{}\r\n", matches.len());
+                for m in matches {
+                    resp.push_str(if options.with_match_len {
+                        "*3\r\n"
+                    } else {
+                        "*2\r\n"
+                    });
+                    resp.push_str(&format!("*2\r\n:{}\r\n:{}\r\n", m.a.0, m.a.1));
+                    resp.push_str(&format!("*2\r\n:{}\r\n:{}\r\n", m.b.0, m.b.1));
+                    if options.with_match_len {
+                        resp.push_str(&format!(":{}\r\n", m.len()));
+                    }
+                }
+                resp.push_str(&format!("$3\r\nlen\r\n:{}\r\n", result.subsequence.len()));
+                resp
+            } else {
+                return Ok(resp::encode_bulk_string(Some(&result.subsequence)));
+            }
+        }
+        Command::MemoryUsage(key, samples) => match db.memory_usage(&key, samples).await {
+            Some(bytes) => format!(":{}\r\n", bytes),
+            None => "$-1\r\n".to_string(),
+        },
         Command::ConfigGet(key) => match db.config_get(key.as_str()).await {
             Some(value) => {
                 format!(
@@ -69,51 +230,218 @@ async fn execute_command(
             }
             None => "$-1\r\n".to_string(),
         },
-        Command::Unknown => "-ERR unknown command\r\n".to_string(),
+        Command::Info(sections) => {
+            let wanted = |name: &str| {
+                sections.is_empty()
+                    || sections.iter().any(|section| {
+                        [name, "default", "all", "everything"].contains(&section.as_str())
+                    })
+            };
+            // sections are separated by a blank line, in Redis order
+            let mut parts = Vec::new();
+            if wanted("memory") {
+                parts.push(info_memory(db).await);
+            }
+            if wanted("persistence") {
+                parts.push(info_persistence(db));
+            }
+            let info = parts.join("\r\n");
+            return Ok(resp::encode_bulk_string(Some(info.as_bytes())));
+        }
+        Command::Auth(user, password) => match &db.config().requirepass {
+            None if user.is_none() => return Err(RespError::AuthNotConfigured),
+            // without a password the default user accepts anything
+            None => "+OK\r\n".to_string(),
+            Some(requirepass) => {
+                if user.as_deref().unwrap_or("default") != "default" || password != *requirepass {
+                    return Err(RespError::WrongPass);
+                }
+                session.authenticated = true;
+                "+OK\r\n".to_string()
+            }
+        },
+        // there is only the default user until real ACLs exist
+        Command::AclWhoami => "$7\r\ndefault\r\n".to_string(),
+        Command::AclList => {
+            let password = match &db.config().requirepass {
+                Some(requirepass) => format!(">{}", requirepass),
+                None => "nopass".to_string(),
+            };
+            let rule = format!("user default on {} ~* &* +@all", password);
+            format!("*1\r\n${}\r\n{}\r\n", rule.len(), rule)
+        }
+        Command::ClientList => {
+            let mut list = String::new();
+            for (id, addr) in db.clients().list().await {
+                list.push_str(&format!("id={} addr={}\n", id, addr));
+            }
+            format!("${}\r\n{}\r\n", list.len(), list)
+        }
+        Command::ClientNoTouch(on) => {
+            session.no_touch = on;
+            "+OK\r\n".to_string()
+        }
+        Command::ClientNoEvict(on) => {
+            session.no_evict = on;
+            "+OK\r\n".to_string()
+        }
+        Command::ObjectIdletime(key) => match db.idle_time(&key).await {
+            Some(seconds) => format!(":{}\r\n", seconds),
+            None => "$-1\r\n".to_string(),
+        },
+        // with no replicas there is nothing to wait for, whatever the timeout
+        Command::Wait => ":0\r\n".to_string(),
+        // there is no AOF and no replicas, so nothing can ever be acknowledged
+        Command::WaitAof(numlocal) => {
+            if numlocal > 0 {
+                return Err(RespError::AofDisabled);
+            }
+            "*2\r\n:0\r\n:0\r\n".to_string()
+        }
+        Command::DebugExpireCycle => {
+            let purged = db.purge_expired().await;
+            format!("+{}\r\n", purged)
+        }
+        Command::DebugObject(key) => match db.debug_object(&key).await {
+            Some(info) => format!(
+                "+Value at:0x0 refcount:1 encoding:{} serializedlength:{} lru:{} lru_seconds_idle:{}\r\n",
+                info.encoding, info.serialized_length, info.lru, info.lru_seconds_idle
+            ),
+            None => return Err(RespError::NoSuchKey),
+        },
+        Command::CmdInfo(names) => {
+            let mut resp = format!("*{}\r\n", names.len());
+            for name in names {
+                let Some(spec) = command::lookup(&name) else {
+                    resp.push_str("*-1\r\n");
+                    continue;
+                };
+                resp.push_str(&format!(
+                    "*6\r\n${}\r\n{}\r\n:{}\r\n*{}\r\n",
+                    spec.name.len(),
+                    spec.name,
+                    spec.arity,
+                    spec.flags.len()
+                ));
+                for flag in spec.flags {
+                    resp.push_str(&format!("+{}\r\n", flag));
+                }
+                resp.push_str(&format!(
+                    ":{}\r\n:{}\r\n:{}\r\n",
+                    spec.first_key, spec.last_key, spec.step
+                ));
+            }
+            resp
+        }
+        Command::CmdCount => format!(":{}\r\n", command::COMMAND_TABLE.len()),
+        Command::Scripting => return Err(RespError::NoScripting),
+        Command::Unknown => return Err(RespError::UnknownCommand),
     };
 
-    stream.write_all(resp.as_bytes()).await?;
-    Ok(())
+    Ok(resp.into_bytes())
+}
+
+async fn handle_stream(stream: TcpStream, addr: SocketAddr, db: &Database) -> Result<(), Error> {
+    let id = db.clients().register(addr).await;
+    println!("accepted new connection {} from {}", id, addr);
+    let result = serve_client(stream, addr, db).await;
+    db.clients().unregister(id).await;
+    println!("connection {} from {} closed", id, addr);
+    result
 }
 
-async fn handle_stream(stream: TcpStream, db: &Database) -> Result<(), Error> {
+async fn serve_client(stream: TcpStream, addr: SocketAddr, db: &Database) -> Result<(), Error> {
     let mut stream = stream;
+    let denied = db.config().denies_peer(addr.ip());
+    let mut session = Session::new(db.config());
     let mut buf = [0; 1024];
-    while let Ok(n) = stream.read(&mut buf).await {
+    // bytes of a frame that has not fully arrived yet
+    let mut pending: Vec<u8> = Vec::new();
+    let limits = db.config().limits();
+    let mut scan = FrameScan::default();
+    'conn: while let Ok(n) = stream.read(&mut buf).await {
         if n == 0 {
             break;
         }
+        pending.extend_from_slice(&buf[..n]);
 
-        match parse_command(&buf[..n]).await {
-            Ok(cmd) => execute_command(&mut stream, cmd, db).await?,
+        // a read may carry several pipelined frames and the start of another
+        let mut pos = 0;
+        while pos < pending.len() {
+            let parsed = match scan.advance(&pending[pos..], &limits) {
+                Ok(()) => parse_frame(&pending[pos..], &limits).await,
+                Err(e) => Err(e),
+            };
+            let frame = match parsed {
+                Ok(frame) => frame,
+                Err(e) if parse::is_incomplete(&e) => break,
+                Err(e) => {
+                    println!("error: {}", e);
+                    stream
+                        .write_all(format!("-ERR {}\r\n", e).as_bytes())
+                        .await?;
+                    break 'conn;
+                }
+            };
+            pos += frame.consumed();
+            scan = FrameScan::default();
 
-            Err(e) => {
-                println!("error: {}", e);
-                break;
-            }
+            let result = match frame.command {
+                _ if denied => Err(RespError::Denied),
+                Ok(cmd) => execute_command(&mut session, cmd, db).await,
+                Err(e) => Err(e),
+            };
+            let reply = match result {
+                Ok(reply) => reply,
+                Err(RespError::Io(e)) => return Err(e),
+                Err(e) => format!("-{}\r\n", e).into_bytes(),
+            };
+            stream.write_all(&reply).await?;
         }
+        pending.drain(..pos);
     }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() {
-    let db = Database::new();
+// replies are small and written whole, so don't let Nagle hold them back;
+// keepalive probes after `keepalive` idle seconds spot dead peers
+fn configure_socket(stream: &TcpStream, keepalive: u64) -> Result<(), Error> {
+    stream.set_nodelay(true)?;
+    let socket = SockRef::from(stream);
+    if keepalive == 0 {
+        return socket.set_keepalive(false);
+    }
+    socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(Duration::from_secs(keepalive)))
+}
 
-    let db = Arc::new(db);
+pub async fn run_server(config: Config, shutdown: impl Future<Output = ()>) -> Result<(), Error> {
+    let listener = TcpListener::bind((config.bind.as_str(), config.port)).await?;
+    serve(listener, config, shutdown).await
+}
 
-    let listener = TcpListener::bind("127.0.0.1:6379")
-        .await
-        .expect("failed to bind");
+// accept loop over an already bound listener, so callers can bind port 0 and
+// read the real port back before serving
+async fn serve(
+    listener: TcpListener,
+    config: Config,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), Error> {
+    let db = Database::open(config);
 
+    tokio::pin!(shutdown);
     loop {
-        let stream = listener.accept().await;
+        let stream = tokio::select! {
+            _ = &mut shutdown => break,
+            stream = listener.accept() => stream,
+        };
         match stream {
-            Ok((_stream, _)) => {
-                println!("accepted new connection");
+            Ok((_stream, addr)) => {
+                if let Err(e) = configure_socket(&_stream, db.config().tcp_keepalive) {
+                    println!("error: {}", e);
+                }
                 let db = Arc::clone(&db); // Move this line outside of the loop
                 spawn(async move {
-                    if let Err(e) = handle_stream(_stream, &db).await {
+                    if let Err(e) = handle_stream(_stream, addr, &db).await {
                         println!("error: {}", e);
                     }
                 });
@@ -123,5 +451,983 @@ async fn main() {
             }
         }
     }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    run_server(config, shutdown).await.expect("failed to bind");
+}
+// i solved all forgot to commit
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_execute_command_returns_reply() {
+        let config = Config::default();
+        let mut session = Session::new(&config);
+        let db = Database::new(config);
+        db.set("foo", "bar").await;
+
+        let reply = execute_command(&mut session, Command::Get("foo".to_string()), &db).await;
+        assert_eq!(reply.unwrap(), b"$3\r\nbar\r\n");
+        let reply = execute_command(&mut session, Command::Get("nope".to_string()), &db).await;
+        assert_eq!(reply.unwrap(), b"$-1\r\n");
+        let reply = execute_command(&mut session, Command::Unknown, &db).await;
+        assert!(matches!(reply, Err(RespError::UnknownCommand)));
+    }
+
+    #[tokio::test]
+    async fn test_loading_rejects_all_but_loading_commands() {
+        let config = Config::default();
+        let mut session = Session::new(&config);
+        let db = Database::new(config);
+        db.set_loading(true);
+
+        let set = Command::Set("k".to_string(), b"v".to_vec(), SetOptions::default());
+        let reply = execute_command(&mut session, set, &db).await;
+        assert!(matches!(reply, Err(RespError::Loading)));
+        let reply = execute_command(&mut session, Command::Get("k".to_string()), &db).await;
+        assert!(matches!(reply, Err(RespError::Loading)));
+        let reply = execute_command(&mut session, Command::Ping, &db).await;
+        assert_eq!(reply.unwrap(), b"+PONG\r\n");
+        let config_get = Command::ConfigGet("port".to_string());
+        assert!(execute_command(&mut session, config_get, &db).await.is_ok());
+
+        db.set_loading(false);
+        let reply = execute_command(&mut session, Command::Get("k".to_string()), &db).await;
+        assert_eq!(reply.unwrap(), b"$-1\r\n");
+    }
+
+    async fn connect(db: Arc<Database>) -> TcpStream {
+        connect_as(db, None).await
+    }
+
+    // `peer` overrides the address handle_stream sees for the client
+    async fn connect_as(db: Arc<Database>, peer: Option<SocketAddr>) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn(async move {
+            let (stream, addr) = listener.accept().await.unwrap();
+            handle_stream(stream, peer.unwrap_or(addr), &db)
+                .await
+                .unwrap();
+        });
+        TcpStream::connect(addr).await.unwrap()
+    }
+
+    async fn request(stream: &mut TcpStream, req: &[u8]) -> String {
+        stream.write_all(req).await.unwrap();
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..n]).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_command_error_keeps_connection_open() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+
+        let resp = request(&mut stream, b"*1\r\n$3\r\nfoo\r\n").await;
+        assert_eq!(resp, "-ERR unknown command\r\n");
+
+        let resp = request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await;
+        assert_eq!(resp, "+PONG\r\n");
+    }
+
+    // a full server on an ephemeral port, shut down by `stop`
+    struct TestServer {
+        addr: SocketAddr,
+        shutdown: tokio::sync::oneshot::Sender<()>,
+        handle: tokio::task::JoinHandle<Result<(), Error>>,
+    }
+
+    impl TestServer {
+        async fn start(config: Config) -> Self {
+            let listener = TcpListener::bind((config.bind.as_str(), 0)).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (shutdown, rx) = tokio::sync::oneshot::channel::<()>();
+            let handle = spawn(serve(listener, config, async {
+                let _ = rx.await;
+            }));
+            TestServer {
+                addr,
+                shutdown,
+                handle,
+            }
+        }
+
+        async fn client(&self) -> TcpStream {
+            TcpStream::connect(self.addr).await.unwrap()
+        }
+
+        async fn stop(self) {
+            self.shutdown.send(()).unwrap();
+            self.handle.await.unwrap().unwrap();
+        }
+    }
+
+    // sends the arguments as a RESP array and returns the raw reply
+    async fn send(stream: &mut TcpStream, args: &[&str]) -> String {
+        let mut req = format!("*{}\r\n", args.len());
+        for arg in args {
+            req.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        request(stream, req.as_bytes()).await
+    }
+
+    #[tokio::test]
+    async fn test_binary_values_round_trip() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        // not UTF-8, and with a CRLF inside
+        let value = [0xff, 0x00, 0xc3, b'\r', b'\n'];
+        let mut set = b"*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$5\r\n".to_vec();
+        set.extend_from_slice(&value);
+        set.extend_from_slice(b"\r\n");
+        assert_eq!(request(&mut stream, &set).await, "+OK\r\n");
+
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$1\r\nb\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 64];
+        let n = stream.read(&mut buf).await.unwrap();
+        let mut expected = b"$5\r\n".to_vec();
+        expected.extend_from_slice(&value);
+        expected.extend_from_slice(b"\r\n");
+        assert_eq!(&buf[..n], expected);
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_non_utf8_keys_are_refused() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        for (key, value) in [(b"\xff", b"1"), (b"\xfe", b"2")] {
+            let mut set = b"*3\r\n$3\r\nSET\r\n$1\r\n".to_vec();
+            set.extend_from_slice(key);
+            set.extend_from_slice(b"\r\n$1\r\n");
+            set.extend_from_slice(value);
+            set.extend_from_slice(b"\r\n");
+            let resp = request(&mut stream, &set).await;
+            assert_eq!(resp, "-ERR keys and text arguments must be valid UTF-8\r\n");
+        }
+        assert_eq!(send(&mut stream, &["DBSIZE"]).await, ":0\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_server_ping_set_get() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        assert_eq!(send(&mut stream, &["PING"]).await, "+PONG\r\n");
+        assert_eq!(send(&mut stream, &["SET", "foo", "bar"]).await, "+OK\r\n");
+        assert_eq!(send(&mut stream, &["GET", "foo"]).await, "$3\r\nbar\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_server_px_expiry() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        let resp = send(&mut stream, &["SET", "foo", "bar", "PX", "50"]).await;
+        assert_eq!(resp, "+OK\r\n");
+        assert_eq!(send(&mut stream, &["GET", "foo"]).await, "$3\r\nbar\r\n");
+        tokio::time::sleep(std::time::Duration::from_millis(80)).await;
+        assert_eq!(send(&mut stream, &["GET", "foo"]).await, "$-1\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_server_shares_keyspace_between_clients() {
+        let server = TestServer::start(Config::default()).await;
+        let mut writer = server.client().await;
+        let mut reader = server.client().await;
+        assert_eq!(send(&mut writer, &["SET", "foo", "bar"]).await, "+OK\r\n");
+        assert_eq!(send(&mut reader, &["GET", "foo"]).await, "$3\r\nbar\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_configure_socket_sets_nodelay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        assert!(!accepted.nodelay().unwrap());
+        configure_socket(&accepted, 300).unwrap();
+        assert!(accepted.nodelay().unwrap());
+        assert!(SockRef::from(&accepted).keepalive().unwrap());
+        configure_socket(&accepted, 0).unwrap();
+        assert!(!SockRef::from(&accepted).keepalive().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_server_stop_closes_listener() {
+        let server = TestServer::start(Config::default()).await;
+        let addr = server.addr;
+        server.stop().await;
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_frame_split_across_reads() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+
+        stream
+            .write_all(b"*2\r\n$4\r\nECHO\r\n$3\r\nf")
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let resp = request(&mut stream, b"oo\r\n").await;
+        assert_eq!(resp, "$3\r\nfoo\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_protected_mode_denies_remote_peer() {
+        let config = Config {
+            bind: "0.0.0.0".to_string(),
+            ..Config::default()
+        };
+        let db = Arc::new(Database::new(config));
+
+        let remote = "10.0.0.5:40000".parse().unwrap();
+        let mut stream = connect_as(Arc::clone(&db), Some(remote)).await;
+        let resp = request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await;
+        assert!(resp.starts_with("-DENIED "));
+
+        let mut stream = connect(db).await;
+        let resp = request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await;
+        assert_eq!(resp, "+PONG\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_client_registry_records_peer_addr() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(Arc::clone(&db)).await;
+        let local = stream.local_addr().unwrap();
+
+        let resp = request(&mut stream, b"*2\r\n$6\r\nCLIENT\r\n$4\r\nLIST\r\n").await;
+        assert!(resp.contains(&format!("addr={}\n", local)));
+        let clients = db.clients().list().await;
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].1, local);
+
+        drop(stream);
+        for _ in 0..100 {
+            if db.clients().list().await.is_empty() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        panic!("client was not unregistered after disconnect");
+    }
+
+    #[tokio::test]
+    async fn test_auth_with_requirepass() {
+        let config = Config {
+            requirepass: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let db = Arc::new(Database::new(config));
+        let mut stream = connect(db).await;
+
+        let resp = request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await;
+        assert_eq!(resp, "-NOAUTH Authentication required.\r\n");
+
+        let resp = request(&mut stream, b"*2\r\n$4\r\nAUTH\r\n$5\r\nwrong\r\n").await;
+        assert!(resp.starts_with("-WRONGPASS "));
+        let resp = request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await;
+        assert_eq!(resp, "-NOAUTH Authentication required.\r\n");
+
+        let resp = request(
+            &mut stream,
+            b"*3\r\n$4\r\nAUTH\r\n$7\r\ndefault\r\n$6\r\nsecret\r\n",
+        )
+        .await;
+        assert_eq!(resp, "+OK\r\n");
+        let resp = request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await;
+        assert_eq!(resp, "+PONG\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_auth_without_requirepass() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+
+        let resp = request(&mut stream, b"*2\r\n$4\r\nAUTH\r\n$6\r\nsecret\r\n").await;
+        assert!(resp.starts_with("-ERR AUTH <password> called without"));
+    }
+
+    #[tokio::test]
+    async fn test_acl_default_user() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+
+        let resp = request(&mut stream, b"*2\r\n$3\r\nACL\r\n$6\r\nWHOAMI\r\n").await;
+        assert_eq!(resp, "$7\r\ndefault\r\n");
+        let resp = request(&mut stream, b"*2\r\n$3\r\nACL\r\n$4\r\nLIST\r\n").await;
+        assert_eq!(resp, "*1\r\n$34\r\nuser default on nopass ~* &* +@all\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_acl_list_reflects_requirepass() {
+        let config = Config {
+            requirepass: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let db = Arc::new(Database::new(config));
+        let mut stream = connect(db).await;
+
+        request(&mut stream, b"*2\r\n$4\r\nAUTH\r\n$6\r\nsecret\r\n").await;
+        let resp = request(&mut stream, b"*2\r\n$3\r\nACL\r\n$4\r\nLIST\r\n").await;
+        assert!(resp.ends_with("user default on >secret ~* &* +@all\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_wait_without_replicas_returns_at_once() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        let started = std::time::Instant::now();
+        assert_eq!(send(&mut stream, &["WAIT", "1", "1000"]).await, ":0\r\n");
+        assert_eq!(send(&mut stream, &["WAIT", "0", "0"]).await, ":0\r\n");
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+        let resp = send(&mut stream, &["WAIT", "x", "0"]).await;
+        assert_eq!(resp, "-ERR value is not an integer or out of range\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_waitaof_without_aof() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+
+        let resp = request(
+            &mut stream,
+            b"*4\r\n$7\r\nWAITAOF\r\n$1\r\n1\r\n$1\r\n0\r\n$3\r\n100\r\n",
+        )
+        .await;
+        assert!(resp.starts_with("-ERR WAITAOF cannot be used"));
+
+        let resp = request(
+            &mut stream,
+            b"*4\r\n$7\r\nWAITAOF\r\n$1\r\n0\r\n$1\r\n0\r\n$3\r\n100\r\n",
+        )
+        .await;
+        assert_eq!(resp, "*2\r\n:0\r\n:0\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_non_numeric_px_replies_error() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+
+        let resp = request(
+            &mut stream,
+            b"*5\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n$2\r\nPX\r\n$3\r\nabc\r\n",
+        )
+        .await;
+        assert_eq!(resp, "-ERR value is not an integer or out of range\r\n");
+        let resp = request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await;
+        assert_eq!(resp, "+PONG\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_memory_usage_reply() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+
+        request(
+            &mut stream,
+            b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n",
+        )
+        .await;
+        let resp = request(
+            &mut stream,
+            b"*3\r\n$6\r\nMEMORY\r\n$5\r\nUSAGE\r\n$3\r\nfoo\r\n",
+        )
+        .await;
+        assert!(resp.starts_with(':'));
+        assert!(resp[1..resp.len() - 2].parse::<usize>().unwrap() > 0);
+
+        let resp = request(
+            &mut stream,
+            b"*3\r\n$6\r\nMEMORY\r\n$5\r\nUSAGE\r\n$4\r\nnope\r\n",
+        )
+        .await;
+        assert_eq!(resp, "$-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_lcs_replies() {
+        let db = Arc::new(Database::new(Config::default()));
+        db.set("key1", "ohmytext").await;
+        db.set("key2", "mynewtext").await;
+        let mut stream = connect(db).await;
+
+        let resp = request(
+            &mut stream,
+            b"*3\r\n$3\r\nLCS\r\n$4\r\nkey1\r\n$4\r\nkey2\r\n",
+        )
+        .await;
+        assert_eq!(resp, "$6\r\nmytext\r\n");
+
+        let resp = request(
+            &mut stream,
+            b"*4\r\n$3\r\nLCS\r\n$4\r\nkey1\r\n$4\r\nkey2\r\n$3\r\nLEN\r\n",
+        )
+        .await;
+        assert_eq!(resp, ":6\r\n");
+
+        let resp = request(
+            &mut stream,
+            b"*7\r\n$3\r\nLCS\r\n$4\r\nkey1\r\n$4\r\nkey2\r\n$3\r\nIDX\r\n$11\r\nMINMATCHLEN\r\n$1\r\n4\r\n$12\r\nWITHMATCHLEN\r\n",
+        )
+        .await;
+        assert_eq!(
+            resp,
+            "*4\r\n$7\r\nmatches\r\n*1\r\n*3\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n:4\r\n$3\r\nlen\r\n:6\r\n"
+        );
+
+        let resp = request(
+            &mut stream,
+            b"*4\r\n$3\r\nLCS\r\n$4\r\nkey1\r\n$4\r\nnone\r\n$3\r\nIDX\r\n",
+        )
+        .await;
+        assert_eq!(resp, "*4\r\n$7\r\nmatches\r\n*0\r\n$3\r\nlen\r\n:0\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_lcs_binary_subsequence() {
+        let db = Arc::new(Database::new(Config::default()));
+        for (key, value) in [("a", vec![0xff, b'x', 0xfe]), ("b", vec![0xff, 0xfe, b'y'])] {
+            let options = SetOptions::default();
+            db.set_owned(key.to_string(), value, options).await.unwrap();
+        }
+        let mut stream = connect(db).await;
+
+        stream
+            .write_all(b"*3\r\n$3\r\nLCS\r\n$1\r\na\r\n$1\r\nb\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 64];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"$2\r\n\xff\xfe\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_debug_gated_by_flag() {
+        let debug = b"*2\r\n$5\r\nDEBUG\r\n$12\r\nEXPIRE-CYCLE\r\n";
+
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+        let resp = request(&mut stream, debug).await;
+        assert!(resp.starts_with("-ERR DEBUG command not allowed"));
+
+        let config = Config {
+            enable_debug_command: true,
+            ..Config::default()
+        };
+        let db = Arc::new(Database::new(config));
+        db.set_with_expire("foo", "bar", 1).await;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        let mut stream = connect(db).await;
+        let resp = request(&mut stream, debug).await;
+        assert_eq!(resp, "+1\r\n");
+
+        let resp = request(
+            &mut stream,
+            b"*3\r\n$5\r\nDEBUG\r\n$6\r\nOBJECT\r\n$3\r\nfoo\r\n",
+        )
+        .await;
+        assert_eq!(resp, "-ERR no such key\r\n");
+        request(
+            &mut stream,
+            b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n",
+        )
+        .await;
+        let resp = request(
+            &mut stream,
+            b"*3\r\n$5\r\nDEBUG\r\n$6\r\nOBJECT\r\n$3\r\nfoo\r\n",
+        )
+        .await;
+        assert!(resp.contains(" encoding:embstr serializedlength:4 "));
+    }
+
+    #[tokio::test]
+    async fn test_command_info_reply() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+        let resp = request(
+            &mut stream,
+            b"*4\r\n$7\r\nCOMMAND\r\n$4\r\nINFO\r\n$3\r\nDEL\r\n$4\r\nnope\r\n",
+        )
+        .await;
+        assert_eq!(
+            resp,
+            "*2\r\n*6\r\n$3\r\ndel\r\n:-2\r\n*1\r\n+write\r\n:1\r\n:-1\r\n:1\r\n*-1\r\n"
+        );
+        let resp = request(&mut stream, b"*2\r\n$7\r\nCOMMAND\r\n$5\r\nCOUNT\r\n").await;
+        assert_eq!(resp, format!(":{}\r\n", command::COMMAND_TABLE.len()));
+    }
+
+    #[tokio::test]
+    async fn test_client_no_touch() {
+        let db = Database::new(Config::default());
+        let mut session = Session::new(db.config());
+        db.set("foo", "bar").await;
+
+        let on = Command::ClientNoTouch(true);
+        let reply = execute_command(&mut session, on, &db).await.unwrap();
+        assert_eq!(reply, b"+OK\r\n");
+        let evict = Command::ClientNoEvict(true);
+        let reply = execute_command(&mut session, evict, &db).await.unwrap();
+        assert_eq!(reply, b"+OK\r\n");
+        assert!(session.no_touch && session.no_evict);
+
+        let idle = || Command::ObjectIdletime("foo".to_string());
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        execute_command(&mut session, Command::Get("foo".to_string()), &db)
+            .await
+            .unwrap();
+        let reply = execute_command(&mut session, idle(), &db).await.unwrap();
+        assert_eq!(reply, b":1\r\n");
+
+        session.no_touch = false;
+        execute_command(&mut session, Command::Get("foo".to_string()), &db)
+            .await
+            .unwrap();
+        let reply = execute_command(&mut session, idle(), &db).await.unwrap();
+        assert_eq!(reply, b":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_commands() {
+        let db = Arc::new(Database::new(Config::default()));
+        let mut stream = connect(db).await;
+        stream
+            .write_all(b"*1\r\n$4\r\nPING\r\n*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*2\r\n$3\r\nGET\r\n$1\r\na\r\n")
+            .await
+            .unwrap();
+
+        let expected = b"+PONG\r\n+OK\r\n$1\r\n1\r\n";
+        let mut resp = Vec::new();
+        let mut buf = [0; 1024];
+        while resp.len() < expected.len() {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            resp.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(resp, expected);
+    }
+
+    #[tokio::test]
+    async fn test_get_round_trips_embedded_newline() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        let value = "line1\r\nline2\n";
+        assert_eq!(send(&mut stream, &["SET", "foo", value]).await, "+OK\r\n");
+        let expected = format!("${}\r\n{}\r\n", value.len(), value);
+        assert_eq!(send(&mut stream, &["GET", "foo"]).await, expected);
+        assert_eq!(send(&mut stream, &["ECHO", value]).await, expected);
+        assert_eq!(send(&mut stream, &["GET", "missing"]).await, "$-1\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_keys() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        let cases: &[(&[&str], &str)] = &[
+            (&["EXISTS", "a", "a", "b"], ":3\r\n"),
+            (&["EXISTS", "a", "a", "nope"], ":2\r\n"),
+            (&["DEL", "a", "a"], ":1\r\n"),
+            (&["DEL", "a", "a", "b", "b"], ":2\r\n"),
+            (&["UNLINK", "a", "a"], ":1\r\n"),
+            (&["UNLINK", "a", "b", "a", "nope"], ":2\r\n"),
+        ];
+        for (command, expected) in cases {
+            send(&mut stream, &["MSET", "a", "1", "b", "2"]).await;
+            assert_eq!(&send(&mut stream, command).await, expected, "{:?}", command);
+        }
+        assert_eq!(send(&mut stream, &["EXISTS", "a", "b"]).await, ":0\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_del_exists_replies() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        send(&mut stream, &["SET", "a", "1"]).await;
+        send(&mut stream, &["SET", "b", "2"]).await;
+        assert_eq!(send(&mut stream, &["DEL", "a", "b", "c"]).await, ":2\r\n");
+        assert_eq!(send(&mut stream, &["DEL", "a"]).await, ":0\r\n");
+        assert_eq!(send(&mut stream, &["GET", "a"]).await, "$-1\r\n");
+        let resp = send(&mut stream, &["EXISTS", "b", "b", "a"]).await;
+        assert_eq!(resp, ":0\r\n");
+        send(&mut stream, &["SET", "b", "2"]).await;
+        let resp = send(&mut stream, &["EXISTS", "b", "b", "a"]).await;
+        assert_eq!(resp, ":2\r\n");
+        let resp = send(&mut stream, &["MGET", "b", "a"]).await;
+        assert_eq!(resp, "*2\r\n$1\r\n2\r\n$-1\r\n");
+        assert_eq!(
+            send(&mut stream, &["MSET", "a", "1", "c", "3"]).await,
+            "+OK\r\n"
+        );
+        let resp = send(&mut stream, &["MGET", "a", "b", "c"]).await;
+        assert_eq!(resp, "*3\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n");
+        assert_eq!(send(&mut stream, &["TYPE", "a"]).await, "+string\r\n");
+        assert_eq!(send(&mut stream, &["TYPE", "nope"]).await, "+none\r\n");
+        assert_eq!(send(&mut stream, &["RENAME", "a", "c"]).await, "+OK\r\n");
+        assert_eq!(send(&mut stream, &["RENAMENX", "c", "b"]).await, ":0\r\n");
+        assert_eq!(send(&mut stream, &["RENAMENX", "c", "a"]).await, ":1\r\n");
+        let resp = send(&mut stream, &["RENAME", "nope", "x"]).await;
+        assert_eq!(resp, "-ERR no such key\r\n");
+        let resp = send(&mut stream, &["MSET", "a", "1", "c"]).await;
+        assert_eq!(
+            resp,
+            "-ERR wrong number of arguments for 'mset' command\r\n"
+        );
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_replies() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        for key in ["user:1", "user:2", "session:1"] {
+            send(&mut stream, &["SET", key, "v"]).await;
+        }
+        // keys come back sorted
+        let resp = send(&mut stream, &["KEYS", "*"]).await;
+        assert_eq!(
+            resp,
+            "*3\r\n$9\r\nsession:1\r\n$6\r\nuser:1\r\n$6\r\nuser:2\r\n"
+        );
+        let resp = send(&mut stream, &["KEYS", "user:*"]).await;
+        assert_eq!(resp, "*2\r\n$6\r\nuser:1\r\n$6\r\nuser:2\r\n");
+        assert_eq!(send(&mut stream, &["KEYS", "nope*"]).await, "*0\r\n");
+        // scan order is by hash, not by name
+        let resp = send(&mut stream, &["SCAN", "0"]).await;
+        assert!(resp.starts_with("*2\r\n$1\r\n0\r\n*3\r\n"), "{:?}", resp);
+        for key in ["user:1", "user:2", "session:1"] {
+            assert!(resp.contains(&format!("\r\n{}\r\n", key)));
+        }
+        let resp = send(&mut stream, &["SCAN", "0", "COUNT", "2", "MATCH", "nope*"]).await;
+        assert!(!resp.starts_with("*2\r\n$1\r\n0\r\n"), "{:?}", resp);
+        assert!(resp.ends_with("*0\r\n"));
+        assert_eq!(send(&mut stream, &["DBSIZE"]).await, ":3\r\n");
+        let resp = send(&mut stream, &["FLUSHDB", "NOW"]).await;
+        assert_eq!(resp, "-ERR syntax error\r\n");
+        assert_eq!(send(&mut stream, &["FLUSHDB"]).await, "+OK\r\n");
+        assert_eq!(send(&mut stream, &["DBSIZE"]).await, ":0\r\n");
+        send(&mut stream, &["SET", "a", "1"]).await;
+        assert_eq!(send(&mut stream, &["FLUSHALL", "async"]).await, "+OK\r\n");
+        assert_eq!(send(&mut stream, &["DBSIZE"]).await, ":0\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_maxmemory_rejects_only_denyoom_writes() {
+        let config = Config {
+            maxmemory: 1024,
+            ..Config::default()
+        };
+        let server = TestServer::start(config).await;
+        let mut stream = server.client().await;
+        let value = "x".repeat(100);
+        let mut n = 0;
+        let resp = loop {
+            let resp = send(&mut stream, &["SET", &format!("k{}", n), &value]).await;
+            if resp != "+OK\r\n" {
+                break resp;
+            }
+            n += 1;
+        };
+        assert!(n > 0);
+        assert_eq!(
+            resp,
+            "-OOM command not allowed when used memory > 'maxmemory'.\r\n"
+        );
+        assert!(send(&mut stream, &["APPEND", "k0", "y"])
+            .await
+            .starts_with("-OOM"));
+
+        let resp = send(&mut stream, &["GET", "k0"]).await;
+        assert_eq!(resp, format!("$100\r\n{}\r\n", value));
+        assert_eq!(send(&mut stream, &["DEL", "k0", "k1"]).await, ":2\r\n");
+        // freeing memory lets writes through again
+        let resp = send(&mut stream, &["SET", "k0", "v"]).await;
+        assert_eq!(resp, "+OK\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_scripting_is_rejected() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        let requests: [&[&str]; 4] = [
+            &["EVAL", "return 1", "0"],
+            &["EVALSHA", "abc", "0"],
+            &["SCRIPT", "LOAD", "return 1"],
+            &["FUNCTION", "LIST"],
+        ];
+        for args in requests {
+            assert_eq!(
+                send(&mut stream, args).await,
+                "-ERR This Redis build does not support scripting\r\n"
+            );
+        }
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_incr_decr_replies() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        assert_eq!(send(&mut stream, &["INCR", "n"]).await, ":1\r\n");
+        assert_eq!(send(&mut stream, &["DECR", "n"]).await, ":0\r\n");
+        assert_eq!(send(&mut stream, &["DECR", "n"]).await, ":-1\r\n");
+        send(&mut stream, &["SET", "s", "x"]).await;
+        assert_eq!(
+            send(&mut stream, &["INCR", "s"]).await,
+            "-ERR value is not an integer or out of range\r\n"
+        );
+        send(&mut stream, &["SET", "min", &i64::MIN.to_string()]).await;
+        assert_eq!(
+            send(&mut stream, &["DECR", "min"]).await,
+            "-ERR increment or decrement would overflow\r\n"
+        );
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_append_strlen_replies() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        assert_eq!(send(&mut stream, &["APPEND", "k", "ab"]).await, ":2\r\n");
+        assert_eq!(send(&mut stream, &["APPEND", "k", "cde"]).await, ":5\r\n");
+        assert_eq!(send(&mut stream, &["STRLEN", "k"]).await, ":5\r\n");
+        assert_eq!(send(&mut stream, &["STRLEN", "nope"]).await, ":0\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_set_option_replies() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        assert_eq!(send(&mut stream, &["SET", "k", "v", "XX"]).await, "$-1\r\n");
+        let resp = send(&mut stream, &["SET", "k", "v", "NX", "PX", "500"]).await;
+        assert_eq!(resp, "+OK\r\n");
+        assert_eq!(send(&mut stream, &["SET", "k", "w", "NX"]).await, "$-1\r\n");
+        assert_eq!(send(&mut stream, &["GET", "k"]).await, "$1\r\nv\r\n");
+        assert_eq!(send(&mut stream, &["SETNX", "k", "w"]).await, ":0\r\n");
+        assert_eq!(send(&mut stream, &["SETNX", "n", "w"]).await, ":1\r\n");
+
+        assert_eq!(send(&mut stream, &["TTL", "k"]).await, ":1\r\n");
+        assert_eq!(send(&mut stream, &["PTTL", "n"]).await, ":-1\r\n");
+        assert_eq!(send(&mut stream, &["TTL", "nope"]).await, ":-2\r\n");
+        assert_eq!(send(&mut stream, &["PERSIST", "k"]).await, ":1\r\n");
+        assert_eq!(send(&mut stream, &["TTL", "k"]).await, ":-1\r\n");
+        assert_eq!(send(&mut stream, &["PERSIST", "k"]).await, ":0\r\n");
+        assert_eq!(send(&mut stream, &["EXPIRE", "k", "100"]).await, ":1\r\n");
+        assert_eq!(send(&mut stream, &["TTL", "k"]).await, ":100\r\n");
+        assert_eq!(
+            send(&mut stream, &["PEXPIRE", "nope", "100"]).await,
+            ":0\r\n"
+        );
+        let resp = send(&mut stream, &["PEXPIRE", "k", "0"]).await;
+        assert_eq!(resp, "-ERR invalid expire time in 'pexpire' command\r\n");
+        let resp = send(&mut stream, &["EXPIRE", "k", "-1"]).await;
+        assert_eq!(resp, "-ERR invalid expire time in 'expire' command\r\n");
+        assert_eq!(send(&mut stream, &["EXISTS", "k"]).await, ":1\r\n");
+        send(&mut stream, &["SET", "k", "v"]).await;
+        assert_eq!(
+            send(&mut stream, &["EXPIREAT", "k", "4000000000"]).await,
+            ":1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["PEXPIREAT", "k", "1000"]).await,
+            ":1\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "k"]).await, "$-1\r\n");
+
+        let resp = send(&mut stream, &["SET", "old", "v", "EXAT", "1"]).await;
+        assert_eq!(resp, "+OK\r\n");
+        assert_eq!(send(&mut stream, &["GET", "old"]).await, "$-1\r\n");
+        let resp = send(&mut stream, &["SET", "k", "v", "EX", "1", "PX", "1"]).await;
+        assert_eq!(resp, "-ERR syntax error\r\n");
+        let resp = send(&mut stream, &["SET", "k", "v", "PX", "0"]).await;
+        assert_eq!(resp, "-ERR invalid expire time in 'set' command\r\n");
+
+        assert_eq!(
+            send(&mut stream, &["SET", "g", "1", "GET"]).await,
+            "$-1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SET", "g", "2", "GET"]).await,
+            "$1\r\n1\r\n"
+        );
+        let resp = send(&mut stream, &["SET", "g", "3", "NX", "GET"]).await;
+        assert_eq!(resp, "$1\r\n2\r\n");
+        assert_eq!(send(&mut stream, &["GET", "g"]).await, "$1\r\n2\r\n");
+        let resp = send(&mut stream, &["SET", "h", "1", "XX", "GET"]).await;
+        assert_eq!(resp, "$-1\r\n");
+        assert_eq!(send(&mut stream, &["EXISTS", "h"]).await, ":0\r\n");
+        let resp = send(&mut stream, &["SET", "g", "4", "XX", "GET", "EX", "10"]).await;
+        assert_eq!(resp, "$1\r\n2\r\n");
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_info_persistence() {
+        let config = Config::default();
+        let mut session = Session::new(&config);
+        let db = Database::new(config);
+        let set = Command::Set("k".to_string(), b"v".to_vec(), SetOptions::default());
+        execute_command(&mut session, set, &db).await.unwrap();
+
+        let info =
+            |sections: &[&str]| Command::Info(sections.iter().map(|s| s.to_string()).collect());
+        let reply = execute_command(&mut session, info(&["persistence"]), &db).await;
+        let reply = String::from_utf8(reply.unwrap()).unwrap();
+        assert!(reply.contains("# Persistence\r\n"));
+        assert!(reply.contains("\r\nloading:0\r\n"));
+        assert!(reply.contains("\r\nrdb_changes_since_last_save:1\r\n"));
+        assert!(reply.contains(&format!("\r\nrdb_last_save_time:{}\r\n", db.last_save())));
+        assert!(reply.contains("\r\nrdb_last_bgsave_status:ok\r\n"));
+        assert!(reply.contains("\r\naof_enabled:0\r\n"));
+
+        let reply = execute_command(&mut session, info(&[]), &db).await.unwrap();
+        assert!(String::from_utf8(reply)
+            .unwrap()
+            .contains("rdb_last_save_time:"));
+        let reply = execute_command(&mut session, info(&["keyspace"]), &db).await;
+        assert_eq!(reply.unwrap(), b"$0\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_info_memory() {
+        let config = Config {
+            maxmemory: 1 << 20,
+            ..Config::default()
+        };
+        let mut session = Session::new(&config);
+        let db = Database::new(config);
+        let set = Command::Set("k".to_string(), vec![b'v'; 100], SetOptions::default());
+        execute_command(&mut session, set, &db).await.unwrap();
+
+        let info =
+            |sections: &[&str]| Command::Info(sections.iter().map(|s| s.to_string()).collect());
+        let reply = execute_command(&mut session, info(&["memory"]), &db).await;
+        let reply = String::from_utf8(reply.unwrap()).unwrap();
+        assert!(reply.contains("# Memory\r\n"));
+        assert!(reply.contains(&format!("\r\nused_memory:{}\r\n", db.used_memory().await)));
+        assert!(reply.contains("\r\nmaxmemory:1048576\r\n"));
+        assert!(!reply.contains("# Persistence"));
+
+        let reply = execute_command(&mut session, info(&[]), &db).await.unwrap();
+        let reply = String::from_utf8(reply).unwrap();
+        let memory = reply.find("# Memory").unwrap();
+        let persistence = reply.find("# Persistence").unwrap();
+        assert!(memory < persistence);
+        assert!(reply.contains("\r\n\r\n# Persistence"));
+    }
+
+    #[tokio::test]
+    async fn test_info_counts_only_real_changes() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        async fn changes(stream: &mut TcpStream) -> u64 {
+            let info = send(stream, &["INFO", "persistence"]).await;
+            let line = info
+                .split("\r\n")
+                .find_map(|line| line.strip_prefix("rdb_changes_since_last_save:"))
+                .unwrap();
+            line.parse().unwrap()
+        }
+
+        send(&mut stream, &["SET", "k", "v"]).await;
+        send(&mut stream, &["SET", "n", "abc"]).await;
+        assert_eq!(changes(&mut stream).await, 2);
+        // refused, failed and no-op writes
+        for args in [
+            &["SET", "k", "w", "NX"][..],
+            &["SET", "nope", "w", "XX"],
+            &["DEL", "nope"],
+            &["EXPIRE", "nope", "10"],
+            &["PERSIST", "k"],
+            &["RENAME", "nope", "x"],
+            &["INCR", "n"],
+            &[
+                "BITFIELD", "k", "OVERFLOW", "FAIL", "INCRBY", "u2", "0", "9",
+            ],
+        ] {
+            send(&mut stream, args).await;
+            assert_eq!(changes(&mut stream).await, 2, "{:?}", args);
+        }
+        send(&mut stream, &["MSET", "a", "1", "b", "2"]).await;
+        assert_eq!(changes(&mut stream).await, 4);
+        send(&mut stream, &["DEL", "a", "b", "nope"]).await;
+        assert_eq!(changes(&mut stream).await, 6);
+        server.stop().await;
+    }
+
+    #[test]
+    fn test_set_reply_truth_table() {
+        let old = || Some(b"old".to_vec());
+        // (written, old value, GET) => reply
+        let table: [(bool, Option<Vec<u8>>, bool, &str); 6] = [
+            (true, None, false, "+OK\r\n"),
+            (false, None, false, "$-1\r\n"),
+            (true, None, true, "$-1\r\n"),
+            (true, old(), true, "$3\r\nold\r\n"),
+            (false, None, true, "$-1\r\n"),
+            (false, old(), true, "$3\r\nold\r\n"),
+        ];
+        for (written, old, get, expected) in table {
+            let reply = set_reply(SetOutcome { written, old }, get);
+            assert_eq!(String::from_utf8(reply).unwrap(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bitfield_replies() {
+        let server = TestServer::start(Config::default()).await;
+        let mut stream = server.client().await;
+        let resp = send(
+            &mut stream,
+            &["BITFIELD", "k", "SET", "u8", "0", "255", "GET", "u8", "0"],
+        )
+        .await;
+        assert_eq!(resp, "*2\r\n:0\r\n:255\r\n");
+        let resp = send(&mut stream, &["BITFIELD", "k", "INCRBY", "u8", "0", "10"]).await;
+        assert_eq!(resp, "*1\r\n:9\r\n");
+        let resp = send(
+            &mut stream,
+            &[
+                "BITFIELD", "k", "OVERFLOW", "FAIL", "INCRBY", "u8", "#0", "250",
+            ],
+        )
+        .await;
+        assert_eq!(resp, "*1\r\n$-1\r\n");
+        let resp = send(&mut stream, &["BITFIELD", "k", "GET", "u64", "0"]).await;
+        assert!(resp.starts_with("-ERR Invalid bitfield type."));
+        server.stop().await;
+    }
 }
-// i solved all forgot to commit
\ No newline at end of file
@@ -1,79 +1,682 @@
-use crate::Command;
-use std::io::Error;
+use crate::bitfield::{self, FieldType, Overflow};
+use crate::command::{lookup, Command, ExpireCondition, LcsOptions, SetCondition, SetOptions};
+use crate::error::RespError;
+use crate::store::Expiry;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::time::Duration;
+
+// caps on what a client may declare before we allocate for it
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_bulk_len: usize,
+    pub max_multibulk_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024,
+        }
+    }
+}
 
 struct RESPDataType {}
 impl RESPDataType {
     const BULK_STRING: u8 = b'$'; // 0x24
     const ARRAY: u8 = b'*'; // 0x2a
+    const ATTRIBUTE: u8 = b'|'; // 0x7c
+}
+
+fn invalid_data() -> Error {
+    Error::new(ErrorKind::InvalidData, "invalid data")
+}
+
+// the frame is cut short; the caller should retry once more bytes arrive
+fn incomplete() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "incomplete frame")
 }
 
-async fn parse_lenght(input: &[u8], len: &mut usize) -> usize {
+fn protocol_error(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Protocol error: {}", msg))
+}
+
+pub fn is_incomplete(e: &Error) -> bool {
+    e.kind() == ErrorKind::UnexpectedEof
+}
+
+async fn parse_lenght(input: &[u8], len: &mut usize) -> Result<usize, Error> {
     let mut pos: usize = 0;
     *len = 0;
-    while input[pos] != b'\r' {
-        *len = *len * 10 + (input[pos] - b'0') as usize;
+    loop {
+        match input.get(pos) {
+            None => return Err(incomplete()),
+            Some(b'\r') => break,
+            Some(b) if b.is_ascii_digit() => {
+                *len = len
+                    .checked_mul(10)
+                    .and_then(|len| len.checked_add((b - b'0') as usize))
+                    .ok_or_else(invalid_data)?;
+            }
+            Some(_) => return Err(invalid_data()),
+        }
         pos += 1;
     }
-    pos + 2
+    if input.len() < pos + 2 {
+        return Err(incomplete());
+    }
+    Ok(pos + 2)
 }
 
-async fn parse_bulk_string(input: &[u8], result: &mut String) -> Result<usize, Error> {
-    if input[0] != RESPDataType::BULK_STRING {
-        return Err(Error::new(std::io::ErrorKind::InvalidData, "invalid data"));
+async fn parse_bulk_string(
+    input: &[u8],
+    result: &mut Vec<u8>,
+    max_len: usize,
+) -> Result<usize, Error> {
+    match input.first() {
+        None => return Err(incomplete()),
+        Some(&RESPDataType::BULK_STRING) => {}
+        Some(_) => return Err(invalid_data()),
     }
 
     let mut pos: usize = 1;
     let mut string_lemgth = 0;
-    pos += parse_lenght(&input[pos..], &mut string_lemgth).await;
+    pos += parse_lenght(&input[pos..], &mut string_lemgth).await?;
+    if string_lemgth > max_len {
+        return Err(protocol_error("invalid bulk length"));
+    }
 
-    *result = String::from_utf8_lossy(&input[pos..pos + string_lemgth]).to_string();
+    if input.len() < pos + string_lemgth + 2 {
+        return Err(incomplete());
+    }
+    *result = input[pos..pos + string_lemgth].to_vec();
     Ok(pos + string_lemgth + 2)
 }
 
-async fn parse_array(input: &[u8]) -> Result<Vec<String>, Error> {
-    if input[0] != RESPDataType::ARRAY {
-        return Err(Error::new(std::io::ErrorKind::InvalidData, "invalid data"));
+async fn parse_array(input: &[u8], limits: &Limits) -> Result<(Vec<Vec<u8>>, usize), Error> {
+    match input.first() {
+        None => return Err(incomplete()),
+        Some(&RESPDataType::ARRAY) => {}
+        Some(_) => return Err(invalid_data()),
     }
 
     let mut pos: usize = 1;
     let mut array_len = 0;
-    pos += parse_lenght(&input[pos..], &mut array_len).await;
+    pos += parse_lenght(&input[pos..], &mut array_len).await?;
+    if array_len > limits.max_multibulk_len {
+        return Err(protocol_error("invalid multibulk length"));
+    }
 
-    let mut array: Vec<String> = Vec::with_capacity(array_len);
+    // the count alone is only a claim, so don't reserve for all of it up front
+    let mut array: Vec<Vec<u8>> = Vec::with_capacity(array_len.min(1024));
     for _ in 0..array_len {
-        let mut arg = String::new();
-        pos += parse_bulk_string(&input[pos..], &mut arg).await?;
+        let mut arg = Vec::new();
+        pos += parse_bulk_string(&input[pos..], &mut arg, limits.max_bulk_len).await?;
         array.push(arg);
     }
 
-    Ok(array)
+    Ok((array, pos))
+}
+
+// no header or simple-string line is anywhere near this long; without a cap,
+// a line that never ends would be buffered forever. Inline (non-RESP)
+// commands aren't accepted at all, so this is the only line length limit
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+// the text up to the next CRLF, and the offset just past it
+fn read_line(input: &[u8]) -> Result<(&[u8], usize), Error> {
+    let searched = &input[..input.len().min(MAX_LINE_LEN + 2)];
+    match searched.windows(2).position(|w| w == b"\r\n") {
+        Some(end) => Ok((&input[..end], end + 2)),
+        None if input.len() >= MAX_LINE_LEN + 2 => Err(protocol_error("too big inline request")),
+        None => Err(incomplete()),
+    }
+}
+
+fn read_signed_len(line: &[u8]) -> Result<i64, Error> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(invalid_data)
+}
+
+// deeper aggregates than this are refused rather than recursed into
+const MAX_NESTING: usize = 32;
+
+// container elements MEMORY USAGE looks at when SAMPLES isn't given, as in Redis
+const DEFAULT_MEMORY_SAMPLES: usize = 5;
+
+// walks over one value of any RESP2/RESP3 type without keeping it, returning
+// its length in bytes
+fn skip_value(input: &[u8], limits: &Limits, depth: usize) -> Result<usize, Error> {
+    if depth > MAX_NESTING {
+        return Err(protocol_error("too many nested aggregates"));
+    }
+    let kind = *input.first().ok_or_else(incomplete)?;
+    let (line, mut pos) = read_line(&input[1..])?;
+    pos += 1;
+    match kind {
+        b'+' | b'-' | b':' | b'_' | b'#' | b',' | b'(' => Ok(pos),
+        b'$' | b'!' | b'=' => {
+            let len = read_signed_len(line)?;
+            if len < 0 {
+                return Ok(pos);
+            }
+            let len = len as usize;
+            if len > limits.max_bulk_len {
+                return Err(protocol_error("invalid bulk length"));
+            }
+            if input.len() < pos + len + 2 {
+                return Err(incomplete());
+            }
+            Ok(pos + len + 2)
+        }
+        b'*' | b'~' | b'>' | b'%' | b'|' => {
+            let len = read_signed_len(line)?;
+            if len < 0 {
+                return Ok(pos);
+            }
+            let len = len as usize;
+            if len > limits.max_multibulk_len {
+                return Err(protocol_error("invalid multibulk length"));
+            }
+            // maps and attributes hold key/value pairs
+            let elements = if matches!(kind, b'%' | b'|') {
+                len * 2
+            } else {
+                len
+            };
+            for _ in 0..elements {
+                pos += skip_value(&input[pos..], limits, depth + 1)?;
+            }
+            Ok(pos)
+        }
+        _ => Err(invalid_data()),
+    }
+}
+
+fn parse_int<T: FromStr>(token: &str) -> Result<T, RespError> {
+    token.parse().map_err(|_| RespError::NotInteger)
 }
 
-pub async fn parse_command(input: &[u8]) -> Result<Command, Error> {
-    let tokens = parse_array(input).await?;
+// a relative EXPIRE/PEXPIRE ttl, which must be positive
+fn parse_ttl(token: &str, in_ms: bool, command: &'static str) -> Result<Expiry, RespError> {
+    let n: i64 = parse_int(token)?;
+    if n <= 0 {
+        return Err(RespError::InvalidExpireTime(command));
+    }
+    let ttl = if in_ms {
+        Duration::from_millis(n as u64)
+    } else {
+        Duration::from_secs(n as u64)
+    };
+    Expiry::after(ttl).ok_or(RespError::InvalidExpireTime(command))
+}
 
-    let command = match tokens[0].to_lowercase().as_str() {
+// an absolute EXPIREAT/PEXPIREAT unix time; anything before the epoch is
+// simply in the past
+fn parse_unix_time(token: &str, in_ms: bool, command: &'static str) -> Result<Expiry, RespError> {
+    let n: i64 = parse_int(token)?;
+    let since_epoch = match u64::try_from(n) {
+        Ok(n) if in_ms => Duration::from_millis(n),
+        Ok(n) => Duration::from_secs(n),
+        Err(_) => Duration::ZERO,
+    };
+    Expiry::at_unix(since_epoch).ok_or(RespError::InvalidExpireTime(command))
+}
+
+fn parse_expire_condition(flags: &[String]) -> Result<ExpireCondition, RespError> {
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for flag in flags {
+        match flag.to_lowercase().as_str() {
+            "nx" => nx = true,
+            "xx" => xx = true,
+            "gt" => gt = true,
+            "lt" => lt = true,
+            _ => return Err(RespError::Syntax),
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return Err(RespError::ExpireNxConflict);
+    }
+    if gt && lt {
+        return Err(RespError::ExpireGtLtConflict);
+    }
+    Ok(match (nx, xx, gt, lt) {
+        (true, ..) => ExpireCondition::Nx,
+        (_, _, true, _) => ExpireCondition::Gt,
+        (_, true, _, true) => ExpireCondition::XxLt,
+        (_, _, _, true) => ExpireCondition::Lt,
+        (_, true, ..) => ExpireCondition::Xx,
+        _ => ExpireCondition::Always,
+    })
+}
+
+// i1..i64 or u1..u63
+fn parse_field_type(token: &str) -> Result<FieldType, RespError> {
+    let signed = match token.as_bytes().first() {
+        Some(b'i' | b'I') => true,
+        Some(b'u' | b'U') => false,
+        _ => return Err(RespError::BitfieldType),
+    };
+    let max_bits = if signed { 64 } else { 63 };
+    match token[1..].parse() {
+        Ok(bits) if (1..=max_bits).contains(&bits) => Ok(FieldType { signed, bits }),
+        _ => Err(RespError::BitfieldType),
+    }
+}
+
+// a bit offset, or `#n` for the n-th field of the given type; strings are
+// capped at 512MB like in redis, so the field must end within 2^32 bits
+fn parse_bit_offset(token: &str, field: FieldType) -> Result<u64, RespError> {
+    let offset = match token.strip_prefix('#') {
+        Some(index) => index
+            .parse::<u64>()
+            .ok()
+            .and_then(|index| index.checked_mul(field.bits as u64)),
+        None => token.parse::<u64>().ok(),
+    };
+    match offset {
+        Some(offset)
+            if offset
+                .checked_add(field.bits as u64)
+                .is_some_and(|end| end <= 1 << 32) =>
+        {
+            Ok(offset)
+        }
+        _ => Err(RespError::BitOffset),
+    }
+}
+
+fn parse_on_off(token: &str) -> Result<bool, RespError> {
+    if token.eq_ignore_ascii_case("on") {
+        Ok(true)
+    } else if token.eq_ignore_ascii_case("off") {
+        Ok(false)
+    } else {
+        Err(RespError::Syntax)
+    }
+}
+
+// one complete frame read off the front of the input. `command` carries any
+// error in the arguments, which doesn't change how many bytes the frame took.
+// `raw` is the frame exactly as received, for forwarding it verbatim
+pub struct Frame<'a> {
+    pub command: Result<Command, RespError>,
+    pub raw: &'a [u8],
+}
+
+impl Frame<'_> {
+    pub fn consumed(&self) -> usize {
+        self.raw.len()
+    }
+}
+
+// how much of a partly received frame has been checked, so a frame arriving
+// over many reads is walked once instead of from its start after every read.
+// Nothing is allocated for the frame until `advance` says it is all here
+#[derive(Debug, Default)]
+pub struct FrameScan {
+    // bytes from the start of the frame known to be complete
+    offset: usize,
+    // top-level elements still to come, once the array header has been read
+    remaining: Option<usize>,
+}
+
+impl FrameScan {
+    // Ok once `input` holds the whole frame; `input` must start at the frame
+    // and only ever grow between calls
+    pub fn advance(&mut self, input: &[u8], limits: &Limits) -> Result<(), Error> {
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                while input.get(self.offset) == Some(&RESPDataType::ATTRIBUTE) {
+                    self.offset += skip_value(&input[self.offset..], limits, 0)?;
+                }
+                match input.get(self.offset) {
+                    None => return Err(incomplete()),
+                    Some(&RESPDataType::ARRAY) => {}
+                    Some(_) => return Err(invalid_data()),
+                }
+                let (line, len) = read_line(&input[self.offset + 1..])?;
+                let count = usize::try_from(read_signed_len(line)?).map_err(|_| invalid_data())?;
+                if count > limits.max_multibulk_len {
+                    return Err(protocol_error("invalid multibulk length"));
+                }
+                self.offset += 1 + len;
+                count
+            }
+        };
+        self.remaining = Some(remaining);
+        for _ in 0..remaining {
+            self.offset += skip_value(&input[self.offset..], limits, 1)?;
+            self.remaining = self.remaining.map(|n| n - 1);
+        }
+        Ok(())
+    }
+}
+
+// fails only when the frame is incomplete or breaks the protocol
+pub async fn parse_frame<'a>(input: &'a [u8], limits: &Limits) -> Result<Frame<'a>, Error> {
+    // RESP3 attributes may precede the command; they carry nothing we use
+    let mut pos = 0;
+    while input.get(pos) == Some(&RESPDataType::ATTRIBUTE) {
+        pos += skip_value(&input[pos..], limits, 0)?;
+    }
+    let (tokens, consumed) = parse_array(&input[pos..], limits).await?;
+    Ok(Frame {
+        command: command_from_tokens(tokens),
+        raw: &input[..pos + consumed],
+    })
+}
+
+#[cfg(test)]
+pub async fn parse_command(input: &[u8], limits: &Limits) -> Result<Command, RespError> {
+    parse_frame(input, limits).await?.command
+}
+
+// the arguments of one command, after its name. Tokens are moved out in order
+// as they're taken; taking past the end is a wrong-arity error for that
+// command, never a panic
+struct Args {
+    name: &'static str,
+    len: usize,
+    tokens: std::vec::IntoIter<Vec<u8>>,
+}
+
+impl Args {
+    // the argument count including the command name, however many are taken
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn remaining(&self) -> usize {
+        self.tokens.len()
+    }
+
+    // the next token as is, for values that may be binary
+    fn take_bytes(&mut self) -> Result<Vec<u8>, RespError> {
+        self.tokens.next().ok_or(RespError::WrongArity(self.name))
+    }
+
+    // the next token as text, for keys, keywords and numbers. Keys are kept
+    // as Strings, so anything that isn't UTF-8 is refused rather than mapped
+    // lossily onto some other key
+    fn take(&mut self) -> Result<String, RespError> {
+        String::from_utf8(self.take_bytes()?).map_err(|_| RespError::NotUtf8)
+    }
+
+    // every token not yet taken, as text
+    fn rest(&mut self) -> Result<Vec<String>, RespError> {
+        self.tokens
+            .by_ref()
+            .map(|token| String::from_utf8(token).map_err(|_| RespError::NotUtf8))
+            .collect()
+    }
+}
+
+fn command_from_tokens(tokens: Vec<Vec<u8>>) -> Result<Command, RespError> {
+    let len = tokens.len();
+    let mut tokens = tokens.into_iter();
+    let Some(name) = tokens.next() else {
+        return Ok(Command::Unknown);
+    };
+
+    // command names, subcommands and option keywords are all case-insensitive;
+    // only keys and values keep their case
+    let name = String::from_utf8_lossy(&name).to_lowercase();
+    let Some(spec) = lookup(&name) else {
+        return Ok(match name.as_str() {
+            "eval" | "evalsha" | "eval_ro" | "evalsha_ro" | "script" | "function" | "fcall"
+            | "fcall_ro" => Command::Scripting,
+            _ => Command::Unknown,
+        });
+    };
+    // a positive arity is exact, a negative one a minimum
+    let arity = spec.arity.unsigned_abs() as usize;
+    if len < arity || (spec.arity > 0 && len > arity) {
+        return Err(RespError::WrongArity(spec.name));
+    }
+    let mut args = Args {
+        name: spec.name,
+        len,
+        tokens,
+    };
+
+    let command = match spec.name {
         "ping" => Command::Ping,
-        "echo" if tokens.len() == 2 => Command::Echo(tokens[1].clone()),
-        "set" => match tokens.len() {
-            3 => Command::Set(tokens[1].clone(), tokens[2].clone(), None),
-            5 if tokens[3].to_lowercase() == "px" => {
-                let expiry_in_ms = tokens[4].parse::<u64>().unwrap();
-                Command::Set(tokens[1].clone(), tokens[2].clone(), Some(expiry_in_ms))
+        "echo" => Command::Echo(args.take_bytes()?),
+        "set" => {
+            let (key, value) = (args.take()?, args.take_bytes()?);
+            let mut options = SetOptions::default();
+            let mut rest = args.rest()?.into_iter();
+            while let Some(option) = rest.next() {
+                match option.to_lowercase().as_str() {
+                    "nx" if options.condition != SetCondition::Xx => {
+                        options.condition = SetCondition::Nx;
+                    }
+                    "xx" if options.condition != SetCondition::Nx => {
+                        options.condition = SetCondition::Xx;
+                    }
+                    "keepttl" if options.expires_at.is_none() => options.keep_ttl = true,
+                    "get" => options.get = true,
+                    // EX, PX, EXAT, PXAT and KEEPTTL are mutually exclusive
+                    kind @ ("ex" | "px" | "exat" | "pxat")
+                        if options.expires_at.is_none() && !options.keep_ttl =>
+                    {
+                        let n: i64 = parse_int(&rest.next().ok_or(RespError::Syntax)?)?;
+                        if n <= 0 {
+                            return Err(RespError::InvalidExpireTime("set"));
+                        }
+                        let n = n as u64;
+                        let expires_at = match kind {
+                            "ex" => Expiry::after(Duration::from_secs(n)),
+                            "px" => Expiry::after(Duration::from_millis(n)),
+                            "exat" => Expiry::at_unix(Duration::from_secs(n)),
+                            _ => Expiry::at_unix(Duration::from_millis(n)),
+                        };
+                        options.expires_at =
+                            Some(expires_at.ok_or(RespError::InvalidExpireTime("set"))?);
+                    }
+                    _ => return Err(RespError::Syntax),
+                }
+            }
+            Command::Set(key, value, options)
+        }
+        "setnx" => Command::SetNx(args.take()?, args.take_bytes()?),
+        "get" => Command::Get(args.take()?),
+        "append" => Command::Append(args.take()?, args.take_bytes()?),
+        "bitfield" => {
+            let key = args.take()?;
+            let mut ops = Vec::new();
+            let mut overflow = Overflow::default();
+            let mut rest = args.rest()?.into_iter();
+            while let Some(op) = rest.next() {
+                let mut arg = || rest.next().ok_or(RespError::Syntax);
+                let op = op.to_lowercase();
+                if op == "overflow" {
+                    overflow = match arg()?.to_lowercase().as_str() {
+                        "wrap" => Overflow::Wrap,
+                        "sat" => Overflow::Sat,
+                        "fail" => Overflow::Fail,
+                        _ => return Err(RespError::Syntax),
+                    };
+                    continue;
+                }
+                let field = parse_field_type(&arg()?)?;
+                let offset = parse_bit_offset(&arg()?, field)?;
+                ops.push(match op.as_str() {
+                    "get" => bitfield::Op::Get(field, offset),
+                    "set" => bitfield::Op::Set(field, offset, parse_int(&arg()?)?, overflow),
+                    "incrby" => bitfield::Op::IncrBy(field, offset, parse_int(&arg()?)?, overflow),
+                    _ => return Err(RespError::Syntax),
+                });
+            }
+            Command::BitField(key, ops)
+        }
+        "strlen" => Command::Strlen(args.take()?),
+        "incr" => Command::Incr(args.take()?),
+        "decr" => Command::Decr(args.take()?),
+        "del" => Command::Del(args.rest()?),
+        "unlink" => Command::Unlink(args.rest()?),
+        "exists" => Command::Exists(args.rest()?),
+        "type" => Command::Type(args.take()?),
+        "ttl" => Command::Ttl(args.take()?),
+        "pttl" => Command::PTtl(args.take()?),
+        "persist" => Command::Persist(args.take()?),
+        "rename" => Command::Rename(args.take()?, args.take()?),
+        "renamenx" => Command::RenameNx(args.take()?, args.take()?),
+        command @ ("expire" | "pexpire" | "expireat" | "pexpireat") => {
+            let key = args.take()?;
+            let time = args.take()?;
+            let condition = parse_expire_condition(&args.rest()?)?;
+            match command {
+                "expire" => Command::Expire(key, parse_ttl(&time, false, command)?, condition),
+                "pexpire" => Command::PExpire(key, parse_ttl(&time, true, command)?, condition),
+                "expireat" => {
+                    Command::ExpireAt(key, parse_unix_time(&time, false, command)?, condition)
+                }
+                _ => Command::PExpireAt(key, parse_unix_time(&time, true, command)?, condition),
+            }
+        }
+        "mget" => Command::MGet(args.rest()?),
+        "mset" => {
+            if args.len().is_multiple_of(2) {
+                return Err(RespError::WrongArity("mset"));
+            }
+            let mut pairs = Vec::with_capacity(args.remaining() / 2);
+            while args.remaining() > 0 {
+                pairs.push((args.take()?, args.take_bytes()?));
+            }
+            Command::MSet(pairs)
+        }
+        "keys" => Command::Keys(args.take()?),
+        "dbsize" => Command::DbSize,
+        "flushdb" | "flushall" => {
+            // there is only one database and flushing it never blocks for
+            // long, so ASYNC and SYNC both just flush
+            match args.rest()?.as_slice() {
+                [] => {}
+                [mode] if ["async", "sync"].contains(&mode.to_lowercase().as_str()) => {}
+                _ => return Err(RespError::Syntax),
+            }
+            if spec.name == "flushdb" {
+                Command::FlushDb
+            } else {
+                Command::FlushAll
+            }
+        }
+        "scan" => {
+            let cursor = args.take()?.parse().map_err(|_| RespError::InvalidCursor)?;
+            let (mut pattern, mut count, mut type_filter) = (None, 10, None);
+            let mut rest = args.rest()?.into_iter();
+            while let Some(option) = rest.next() {
+                let value = rest.next().ok_or(RespError::Syntax)?;
+                match option.to_lowercase().as_str() {
+                    "match" => pattern = Some(value),
+                    "count" => {
+                        count = parse_int(&value)?;
+                        if count == 0 {
+                            return Err(RespError::Syntax);
+                        }
+                    }
+                    "type" => type_filter = Some(value),
+                    _ => return Err(RespError::Syntax),
+                }
+            }
+            Command::Scan {
+                cursor,
+                pattern,
+                count,
+                type_filter,
+            }
+        }
+        "memory" => match args.take()?.to_lowercase().as_str() {
+            "usage" => {
+                let key = args.take()?;
+                let samples = match args.rest()?.as_slice() {
+                    [] => DEFAULT_MEMORY_SAMPLES,
+                    [option, samples] if option.eq_ignore_ascii_case("samples") => {
+                        parse_int::<usize>(samples)?
+                    }
+                    _ => return Err(RespError::Syntax),
+                };
+                Command::MemoryUsage(key, samples)
             }
             _ => Command::Unknown,
         },
-        "get" if tokens.len() == 2 => Command::Get(tokens[1].clone()),
-        "keys" if tokens.len() == 2 => Command::Keys(tokens[1].clone()),
-        "config" => {
-            if tokens.len() < 3 {
-                return Ok(Command::Unknown);
+        "lcs" => {
+            let (a, b) = (args.take()?, args.take()?);
+            let mut options = LcsOptions::default();
+            let mut rest = args.rest()?.into_iter();
+            while let Some(option) = rest.next() {
+                match option.to_lowercase().as_str() {
+                    "len" => options.len = true,
+                    "idx" => options.idx = true,
+                    "withmatchlen" => options.with_match_len = true,
+                    "minmatchlen" => {
+                        let len = rest.next().ok_or(RespError::Syntax)?;
+                        // negative lengths behave like 0, as in redis
+                        options.min_match_len = parse_int::<i64>(&len)?.max(0) as usize;
+                    }
+                    _ => return Err(RespError::Syntax),
+                }
             }
-            match tokens[1].to_lowercase().as_str() {
-                "get" => Command::ConfigGet(tokens[2].clone()),
-                _ => Command::Unknown,
+            if options.len && options.idx {
+                return Err(RespError::LcsLenAndIdx);
             }
+            Command::Lcs(a, b, options)
+        }
+        "config" => match args.take()?.to_lowercase().as_str() {
+            "get" => Command::ConfigGet(args.take()?.to_lowercase()),
+            _ => Command::Unknown,
+        },
+        "info" => Command::Info(args.rest()?.iter().map(|s| s.to_lowercase()).collect()),
+        "auth" => match args.len() {
+            2 => Command::Auth(None, args.take()?),
+            3 => Command::Auth(Some(args.take()?), args.take()?),
+            _ => return Err(RespError::Syntax),
+        },
+        "acl" => match (args.take()?.to_lowercase().as_str(), args.len()) {
+            ("whoami", 2) => Command::AclWhoami,
+            ("list", 2) => Command::AclList,
+            _ => Command::Unknown,
+        },
+        "client" => match (args.take()?.to_lowercase().as_str(), args.len()) {
+            ("list", 2) => Command::ClientList,
+            ("no-touch", 3) => Command::ClientNoTouch(parse_on_off(&args.take()?)?),
+            ("no-evict", 3) => Command::ClientNoEvict(parse_on_off(&args.take()?)?),
+            _ => Command::Unknown,
+        },
+        "object" => match (args.take()?.to_lowercase().as_str(), args.len()) {
+            ("idletime", 3) => Command::ObjectIdletime(args.take()?),
+            _ => Command::Unknown,
+        },
+        "wait" => {
+            parse_int::<u64>(&args.take()?)?;
+            parse_int::<u64>(&args.take()?)?;
+            Command::Wait
         }
+        "waitaof" => {
+            let numlocal = parse_int(&args.take()?)?;
+            parse_int::<u64>(&args.take()?)?;
+            parse_int::<u64>(&args.take()?)?;
+            Command::WaitAof(numlocal)
+        }
+        "debug" => match (args.take()?.to_lowercase().as_str(), args.len()) {
+            ("expire-cycle", 2) => Command::DebugExpireCycle,
+            ("object", 3) => Command::DebugObject(args.take()?),
+            _ => Command::Unknown,
+        },
+        "command" => match (
+            args.take().unwrap_or_default().to_lowercase().as_str(),
+            args.len(),
+        ) {
+            ("info", _) => Command::CmdInfo(args.rest()?),
+            ("count", 2) => Command::CmdCount,
+            _ => Command::Unknown,
+        },
         _ => Command::Unknown,
     };
 
@@ -83,12 +686,46 @@ pub async fn parse_command(input: &[u8]) -> Result<Command, Error> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+    // a relative expiry parsed just now has at most `ms` left, give or take scheduling
+    fn assert_ms_left(expires_at: Option<Expiry>, ms: u128) {
+        match expires_at {
+            Some(Expiry::Deadline(deadline)) => {
+                let left = deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_millis();
+                assert!(left <= ms && left + 100 > ms, "{} ms left", left);
+            }
+            other => panic!("expected a relative expiry, got {:?}", other),
+        }
+    }
+
+    fn encode(args: &[&str]) -> Vec<u8> {
+        let mut input = format!("*{}\r\n", args.len());
+        for arg in args {
+            input.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        input.into_bytes()
+    }
+
+    async fn parse_set(args: &[&str]) -> Result<SetOptions, RespError> {
+        let input = encode(&[&["SET", "k", "v"], args].concat());
+        match parse_command(&input, &Limits::default()).await? {
+            Command::Set(_, _, options) => Ok(options),
+            other => panic!("expected SET, got {:?}", other),
+        }
+    }
+
+    async fn parse_args(args: &[&str]) -> Result<Command, RespError> {
+        parse_command(&encode(args), &Limits::default()).await
+    }
 
     #[tokio::test]
     async fn test_parse_lenght() {
         let input = b"123\r\n";
         let mut len = 0;
-        let pos = parse_lenght(input, &mut len).await;
+        let pos = parse_lenght(input, &mut len).await.unwrap();
         assert_eq!(pos, 5);
         assert_eq!(len, 123);
     }
@@ -96,18 +733,620 @@ mod test {
     #[tokio::test]
     async fn test_parse_bulk_string() {
         let input = b"$3\r\nfoo\r\n";
-        let mut result = String::new();
-        let pos = parse_bulk_string(input, &mut result).await.unwrap();
+        let mut result = Vec::new();
+        let pos = parse_bulk_string(input, &mut result, usize::MAX)
+            .await
+            .unwrap();
         assert_eq!(pos, 9);
-        assert_eq!(result, "foo");
+        assert_eq!(result, b"foo");
     }
 
     #[tokio::test]
     async fn test_parse_array() {
         let input = b"*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
-        let result = parse_array(input).await.unwrap();
+        let (result, consumed) = parse_array(input, &Limits::default()).await.unwrap();
+        assert_eq!(consumed, input.len());
         assert_eq!(result.len(), 2);
-        assert_eq!(result[0], "foo");
-        assert_eq!(result[1], "bar");
+        assert_eq!(result[0], b"foo");
+        assert_eq!(result[1], b"bar");
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_parse_empty_bulk_string() {
+        let input = b"$0\r\n\r\n";
+        let mut result = b"stale".to_vec();
+        let pos = parse_bulk_string(input, &mut result, usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(pos, 6);
+        assert!(result.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_parse_refuses_non_utf8_keys() {
+        // lossily these would both become U+FFFD and name the same key
+        for key in [b"\xff", b"\xfe"] {
+            let mut input = b"*3\r\n$3\r\nSET\r\n$1\r\n".to_vec();
+            input.extend_from_slice(key);
+            input.extend_from_slice(b"\r\n$1\r\nv\r\n");
+            let err = parse_command(&input, &Limits::default()).await.unwrap_err();
+            assert!(matches!(err, RespError::NotUtf8));
+
+            let mut input = b"*3\r\n$3\r\nDEL\r\n$1\r\nk\r\n$1\r\n".to_vec();
+            input.extend_from_slice(key);
+            input.extend_from_slice(b"\r\n");
+            let err = parse_command(&input, &Limits::default()).await.unwrap_err();
+            assert!(matches!(err, RespError::NotUtf8));
+        }
+        // values stay binary
+        let input = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\n\xff\r\n";
+        assert!(matches!(
+            parse_command(input, &Limits::default()).await.unwrap(),
+            Command::Set(_, value, _) if value == b"\xff"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_parse_set_empty_key_and_value() {
+        let input = b"*3\r\n$3\r\nSET\r\n$0\r\n\r\n$0\r\n\r\n";
+        match parse_command(input, &Limits::default()).await.unwrap() {
+            Command::Set(key, value, options) => {
+                assert_eq!(options.expires_at, None);
+                assert_eq!(key, "");
+                assert!(value.is_empty());
+            }
+            _ => panic!("expected SET"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_debug_expire_cycle() {
+        let input = b"*2\r\n$5\r\nDEBUG\r\n$12\r\nexpire-cycle\r\n";
+        assert!(matches!(
+            parse_command(input, &Limits::default()).await.unwrap(),
+            Command::DebugExpireCycle
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_parse_bulk_string_incomplete() {
+        let mut result = Vec::new();
+        let err = parse_bulk_string(b"$10\r\nfoo", &mut result, usize::MAX)
+            .await
+            .unwrap_err();
+        assert!(is_incomplete(&err));
+
+        let err = parse_bulk_string(b"$3\r\nfoo", &mut result, usize::MAX)
+            .await
+            .unwrap_err();
+        assert!(is_incomplete(&err));
+
+        let err = parse_bulk_string(b"$1", &mut result, usize::MAX)
+            .await
+            .unwrap_err();
+        assert!(is_incomplete(&err));
+    }
+
+    #[tokio::test]
+    async fn test_parse_array_incomplete() {
+        let err = parse_command(b"*2\r\n$4\r\nECHO\r\n", &Limits::default())
+            .await
+            .unwrap_err();
+        assert!(err.is_incomplete());
+    }
+
+    #[tokio::test]
+    async fn test_parse_lenght_invalid() {
+        let mut len = 0;
+        let err = parse_lenght(b"1x\r\n", &mut len).await.unwrap_err();
+        assert!(!is_incomplete(&err));
+    }
+
+    #[tokio::test]
+    async fn test_multibulk_over_limit() {
+        let limits = Limits {
+            max_multibulk_len: 2,
+            ..Limits::default()
+        };
+        let err = parse_command(b"*3\r\n", &limits).await.unwrap_err();
+        assert!(!err.is_incomplete());
+        assert_eq!(err.to_string(), "Protocol error: invalid multibulk length");
+
+        // rejected from the header alone, without waiting for the elements
+        let err = parse_command(b"*4294967296\r\n", &Limits::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Protocol error: invalid multibulk length");
+    }
+
+    #[test]
+    fn test_frame_scan_resumes() {
+        let frame = b"*2\r\n$4\r\nECHO\r\n$3\r\nfoo\r\n";
+        let mut scan = FrameScan::default();
+        for end in 0..frame.len() {
+            let err = scan.advance(&frame[..end], &Limits::default()).unwrap_err();
+            assert!(is_incomplete(&err), "{} bytes", end);
+            // never past the last element that has fully arrived
+            assert!(scan.offset <= end);
+        }
+        assert_eq!(scan.offset, 14);
+        scan.advance(frame, &Limits::default()).unwrap();
+        assert_eq!(scan.offset, frame.len());
+        assert_eq!(scan.remaining, Some(0));
+
+        let limits = Limits {
+            max_multibulk_len: 2,
+            ..Limits::default()
+        };
+        let err = FrameScan::default()
+            .advance(b"*3\r\n", &limits)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Protocol error: invalid multibulk length");
+        let err = FrameScan::default()
+            .advance(b"+OK\r\n", &limits)
+            .unwrap_err();
+        assert!(!is_incomplete(&err));
+    }
+
+    #[tokio::test]
+    async fn test_line_over_limit() {
+        // a header line that never ends
+        let mut input = b"*".to_vec();
+        input.resize(MAX_LINE_LEN + 10, b'1');
+        let err = FrameScan::default()
+            .advance(&input, &Limits::default())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Protocol error: too big inline request");
+
+        // an attribute's simple string, which is read as a line too
+        let mut input = b"|1\r\n+k\r\n+".to_vec();
+        input.resize(MAX_LINE_LEN + 20, b'a');
+        let err = parse_frame(&input, &Limits::default()).await.err().unwrap();
+        assert_eq!(err.to_string(), "Protocol error: too big inline request");
+
+        let mut input = b"|1\r\n+k\r\n+".to_vec();
+        input.resize(MAX_LINE_LEN, b'a');
+        assert!(is_incomplete(
+            &parse_frame(&input, &Limits::default()).await.err().unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bulk_over_limit() {
+        let limits = Limits {
+            max_bulk_len: 4,
+            ..Limits::default()
+        };
+        let err = parse_command(b"*1\r\n$5\r\n", &limits).await.unwrap_err();
+        assert!(!err.is_incomplete());
+        assert_eq!(err.to_string(), "Protocol error: invalid bulk length");
+
+        let ok = parse_command(b"*1\r\n$4\r\nPING\r\n", &limits).await;
+        assert!(matches!(ok, Ok(Command::Ping)));
+    }
+
+    #[tokio::test]
+    async fn test_non_numeric_arguments() {
+        let inputs: [&[u8]; 3] = [
+            b"*5\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n$2\r\nPX\r\n$3\r\nabc\r\n",
+            b"*4\r\n$7\r\nWAITAOF\r\n$1\r\nx\r\n$1\r\n0\r\n$1\r\n0\r\n",
+            b"*4\r\n$7\r\nWAITAOF\r\n$1\r\n0\r\n$1\r\n0\r\n$1\r\nx\r\n",
+        ];
+        for input in inputs {
+            let err = parse_command(input, &Limits::default()).await.unwrap_err();
+            assert!(matches!(err, RespError::NotInteger));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mixed_case_config_get() {
+        let inputs: [&[u8]; 3] = [
+            b"*3\r\n$6\r\nCONFIG\r\n$3\r\nGET\r\n$3\r\nDIR\r\n",
+            b"*3\r\n$6\r\nconfig\r\n$3\r\nget\r\n$3\r\ndir\r\n",
+            b"*3\r\n$6\r\nConfig\r\n$3\r\nGet\r\n$3\r\nDir\r\n",
+        ];
+        for input in inputs {
+            match parse_command(input, &Limits::default()).await.unwrap() {
+                Command::ConfigGet(key) => assert_eq!(key, "dir"),
+                other => panic!("expected CONFIG GET, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_set_px_reads_the_value_token() {
+        let input = b"*5\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n$2\r\npx\r\n$4\r\n1000\r\n";
+        match parse_command(input, &Limits::default()).await.unwrap() {
+            Command::Set(_, _, options) => assert_ms_left(options.expires_at, 1000),
+            other => panic!("expected SET, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_set_conditions() {
+        let input =
+            b"*6\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n$2\r\nnx\r\n$2\r\nPX\r\n$3\r\n500\r\n";
+        match parse_command(input, &Limits::default()).await.unwrap() {
+            Command::Set(_, _, options) => {
+                assert_eq!(options.condition, SetCondition::Nx);
+                assert_ms_left(options.expires_at, 500);
+            }
+            other => panic!("expected SET, got {:?}", other),
+        }
+
+        let both = b"*5\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n$2\r\nNX\r\n$2\r\nXX\r\n";
+        let err = parse_command(both, &Limits::default()).await.unwrap_err();
+        assert!(matches!(err, RespError::Syntax));
+        let dangling = b"*4\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n$2\r\nPX\r\n";
+        let err = parse_command(dangling, &Limits::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RespError::Syntax));
+    }
+
+    #[tokio::test]
+    async fn test_mixed_case_set_px() {
+        let input = b"*5\r\n$3\r\nsEt\r\n$3\r\nFoo\r\n$3\r\nBar\r\n$2\r\nPx\r\n$3\r\n100\r\n";
+        match parse_command(input, &Limits::default()).await.unwrap() {
+            Command::Set(key, value, options) => {
+                assert_eq!(key, "Foo");
+                assert_eq!(value, b"Bar");
+                assert_ms_left(options.expires_at, 100);
+            }
+            other => panic!("expected SET, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_memory_usage_samples() {
+        let input =
+            b"*5\r\n$6\r\nMEMORY\r\n$5\r\nUSAGE\r\n$3\r\nfoo\r\n$7\r\nSAMPLES\r\n$1\r\n0\r\n";
+        assert!(matches!(
+            parse_command(input, &Limits::default()).await.unwrap(),
+            Command::MemoryUsage(key, 0) if key == "foo"
+        ));
+
+        let input = b"*3\r\n$6\r\nMEMORY\r\n$5\r\nUSAGE\r\n$3\r\nfoo\r\n";
+        assert!(matches!(
+            parse_command(input, &Limits::default()).await.unwrap(),
+            Command::MemoryUsage(key, 5) if key == "foo"
+        ));
+
+        let input =
+            b"*5\r\n$6\r\nMEMORY\r\n$5\r\nUSAGE\r\n$3\r\nfoo\r\n$7\r\nsamples\r\n$2\r\n-1\r\n";
+        assert!(matches!(
+            parse_command(input, &Limits::default()).await,
+            Err(RespError::NotInteger)
+        ));
+
+        let input = b"*4\r\n$6\r\nMEMORY\r\n$5\r\nUSAGE\r\n$3\r\nfoo\r\n$7\r\nSAMPLES\r\n";
+        assert!(matches!(
+            parse_command(input, &Limits::default()).await,
+            Err(RespError::Syntax)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_parse_frame_reports_consumed() {
+        let input = b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPI";
+        let frame = parse_frame(input, &Limits::default()).await.unwrap();
+        assert!(matches!(frame.command, Ok(Command::Ping)));
+        assert_eq!(frame.consumed(), 14);
+
+        // the second frame is cut short
+        let err = parse_frame(&input[14..], &Limits::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(is_incomplete(&err));
+
+        // a bad argument still consumes its whole frame
+        let input = b"*4\r\n$7\r\nWAITAOF\r\n$1\r\nx\r\n$1\r\n0\r\n$1\r\n0\r\n";
+        let frame = parse_frame(input, &Limits::default()).await.unwrap();
+        assert!(matches!(frame.command, Err(RespError::NotInteger)));
+        assert_eq!(frame.consumed(), input.len());
+    }
+
+    #[tokio::test]
+    async fn test_parse_frame_keeps_raw_bytes() {
+        // lowercase name and a mixed-case keyword, which re-encoding would lose
+        let set = b"*5\r\n$3\r\nset\r\n$1\r\nk\r\n$1\r\nv\r\n$2\r\nPx\r\n$3\r\n100\r\n";
+        let mut input = set.to_vec();
+        input.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+
+        let frame = parse_frame(&input, &Limits::default()).await.unwrap();
+        assert_eq!(frame.raw, set);
+        let next = parse_frame(&input[frame.consumed()..], &Limits::default())
+            .await
+            .unwrap();
+        assert_eq!(next.raw, b"*1\r\n$4\r\nPING\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_parse_frame_skips_attributes() {
+        let attribute =
+            b"|1\r\n+key-popularity\r\n%2\r\n$1\r\na\r\n,0.19\r\n$1\r\nb\r\n*2\r\n:1\r\n_\r\n";
+        let mut input = attribute.to_vec();
+        input.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+
+        let frame = parse_frame(&input, &Limits::default()).await.unwrap();
+        assert!(matches!(frame.command, Ok(Command::Ping)));
+        assert_eq!(frame.consumed(), input.len());
+
+        // a cut-off attribute waits for more bytes like any other frame
+        let err = parse_frame(&attribute[..20], &Limits::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(is_incomplete(&err));
+
+        let nested = b"|1\r\n+k\r\n".to_vec();
+        let nested = [nested, b"*1\r\n".repeat(64)].concat();
+        let err = parse_frame(&nested, &Limits::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(!is_incomplete(&err));
+    }
+
+    #[tokio::test]
+    async fn test_parse_expire() {
+        match parse_args(&["EXPIRE", "k", "10"]).await.unwrap() {
+            Command::Expire(key, expiry, ExpireCondition::Always) => {
+                assert_eq!(key, "k");
+                assert_ms_left(Some(expiry), 10_000);
+            }
+            other => panic!("expected EXPIRE, got {:?}", other),
+        }
+        match parse_args(&["pexpire", "k", "250"]).await.unwrap() {
+            Command::PExpire(_, expiry, _) => assert_ms_left(Some(expiry), 250),
+            other => panic!("expected PEXPIRE, got {:?}", other),
+        }
+        for ttl in ["0", "-5"] {
+            let err = parse_args(&["EXPIRE", "k", ttl]).await.unwrap_err();
+            assert!(matches!(err, RespError::InvalidExpireTime("expire")));
+        }
+        let err = parse_args(&["PEXPIRE", "k", "0"]).await.unwrap_err();
+        assert!(matches!(err, RespError::InvalidExpireTime("pexpire")));
+        let err = parse_args(&["EXPIRE", "k", "soon"]).await.unwrap_err();
+        assert!(matches!(err, RespError::NotInteger));
+        let err = parse_args(&["EXPIRE", "k", &i64::MAX.to_string()])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RespError::InvalidExpireTime("expire")));
+    }
+
+    #[tokio::test]
+    async fn test_parse_expireat() {
+        match parse_args(&["EXPIREAT", "k", "2000000000"]).await.unwrap() {
+            Command::ExpireAt(_, expiry, _) => {
+                let at = UNIX_EPOCH + Duration::from_secs(2_000_000_000);
+                assert_eq!(expiry, Expiry::At(at));
+            }
+            other => panic!("expected EXPIREAT, got {:?}", other),
+        }
+        match parse_args(&["pexpireat", "k", "-1"]).await.unwrap() {
+            Command::PExpireAt(_, expiry, _) => assert_eq!(expiry, Expiry::At(UNIX_EPOCH)),
+            other => panic!("expected PEXPIREAT, got {:?}", other),
+        }
+        let err = parse_args(&["EXPIREAT", "k", "1.5"]).await.unwrap_err();
+        assert!(matches!(err, RespError::NotInteger));
+    }
+
+    #[tokio::test]
+    async fn test_parse_expire_conditions() {
+        let condition = |flags: &'static [&'static str]| async move {
+            match parse_args(&[&["EXPIRE", "k", "10"], flags].concat()).await? {
+                Command::Expire(_, _, condition) => Ok(condition),
+                other => panic!("expected EXPIRE, got {:?}", other),
+            }
+        };
+        assert_eq!(condition(&[]).await.unwrap(), ExpireCondition::Always);
+        assert_eq!(condition(&["nx"]).await.unwrap(), ExpireCondition::Nx);
+        assert_eq!(condition(&["XX"]).await.unwrap(), ExpireCondition::Xx);
+        assert_eq!(condition(&["GT", "xx"]).await.unwrap(), ExpireCondition::Gt);
+        assert_eq!(
+            condition(&["XX", "LT"]).await.unwrap(),
+            ExpireCondition::XxLt
+        );
+        let err = condition(&["NX", "GT"]).await.unwrap_err();
+        assert!(matches!(err, RespError::ExpireNxConflict));
+        let err = condition(&["GT", "LT"]).await.unwrap_err();
+        assert!(matches!(err, RespError::ExpireGtLtConflict));
+        let err = condition(&["SOON"]).await.unwrap_err();
+        assert!(matches!(err, RespError::Syntax));
+    }
+
+    #[tokio::test]
+    async fn test_parse_scan() {
+        match parse_args(&["SCAN", "5", "match", "k*", "COUNT", "3", "type", "string"])
+            .await
+            .unwrap()
+        {
+            Command::Scan {
+                cursor,
+                pattern,
+                count,
+                type_filter,
+            } => {
+                assert_eq!(cursor, 5);
+                assert_eq!(pattern.as_deref(), Some("k*"));
+                assert_eq!(count, 3);
+                assert_eq!(type_filter.as_deref(), Some("string"));
+            }
+            other => panic!("expected SCAN, got {:?}", other),
+        }
+        match parse_args(&["SCAN", "0"]).await.unwrap() {
+            Command::Scan { count, pattern, .. } => {
+                assert_eq!(count, 10);
+                assert!(pattern.is_none());
+            }
+            other => panic!("expected SCAN, got {:?}", other),
+        }
+        let err = parse_args(&["SCAN", "x"]).await.unwrap_err();
+        assert!(matches!(err, RespError::InvalidCursor));
+        for args in [&["SCAN", "0", "COUNT", "0"][..], &["SCAN", "0", "MATCH"]] {
+            let err = parse_args(args).await.unwrap_err();
+            assert!(matches!(err, RespError::Syntax), "{:?}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_mset() {
+        let input = encode(&["MSET", "a", "1", "b", "2"]);
+        match parse_command(&input, &Limits::default()).await.unwrap() {
+            Command::MSet(pairs) => assert_eq!(
+                pairs,
+                [("a", "1"), ("b", "2")].map(|(k, v)| (k.to_string(), v.as_bytes().to_vec()))
+            ),
+            other => panic!("expected MSET, got {:?}", other),
+        }
+        for args in [&["MSET", "a"][..], &["mset", "a", "1", "b"]] {
+            let err = parse_command(&encode(args), &Limits::default())
+                .await
+                .unwrap_err();
+            assert!(matches!(err, RespError::WrongArity("mset")));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_too_few_arguments() {
+        use crate::command::COMMAND_TABLE;
+
+        for spec in COMMAND_TABLE {
+            let arity = spec.arity.unsigned_abs() as usize;
+            // the name, then one argument short
+            let mut args = vec![spec.name];
+            args.resize(arity.max(2) - 1, "arg");
+            if arity > 1 {
+                let err = parse_args(&args).await.unwrap_err();
+                assert!(
+                    matches!(err, RespError::WrongArity(name) if name == spec.name),
+                    "{}: {:?}",
+                    spec.name,
+                    err
+                );
+            }
+            if spec.arity > 0 {
+                args.resize(arity + 1, "arg");
+                let err = parse_args(&args).await.unwrap_err();
+                assert!(matches!(err, RespError::WrongArity(_)), "{}", spec.name);
+            }
+        }
+
+        let err = parse_args(&["ECHO"]).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR wrong number of arguments for 'echo' command"
+        );
+        // subcommands that need a further argument
+        for args in [&["CONFIG", "GET"][..], &["MEMORY", "USAGE"]] {
+            let err = parse_args(args).await.unwrap_err();
+            assert!(matches!(err, RespError::WrongArity(_)), "{:?}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_set_expiry_options() {
+        assert_ms_left(parse_set(&["EX", "10"]).await.unwrap().expires_at, 10_000);
+        assert_ms_left(parse_set(&["px", "250"]).await.unwrap().expires_at, 250);
+
+        let exat = parse_set(&["EXAT", "2000000000"]).await.unwrap();
+        let at = UNIX_EPOCH + Duration::from_secs(2_000_000_000);
+        assert_eq!(exat.expires_at, Some(Expiry::At(at)));
+        let pxat = parse_set(&["pxat", "2000000000123"]).await.unwrap();
+        let at = UNIX_EPOCH + Duration::from_millis(2_000_000_000_123);
+        assert_eq!(pxat.expires_at, Some(Expiry::At(at)));
+
+        // already in the past: stored as-is, so the key reads back as expired
+        let past = parse_set(&["EXAT", "1"]).await.unwrap();
+        assert!(matches!(past.expires_at, Some(Expiry::At(at)) if at < SystemTime::now()));
+
+        for conflict in [
+            &["EX", "1", "PX", "1"][..],
+            &["PXAT", "1", "EXAT", "1"],
+            &["KEEPTTL", "PX", "1"],
+            &["EX", "1", "keepttl"],
+        ] {
+            let err = parse_set(conflict).await.unwrap_err();
+            assert!(matches!(err, RespError::Syntax));
+        }
+        assert!(parse_set(&["KEEPTTL", "XX"]).await.unwrap().keep_ttl);
+        assert!(parse_set(&["NX", "get"]).await.unwrap().get);
+
+        for invalid in [
+            &["PX", "0"][..],
+            &["EX", "-1"],
+            &["PXAT", "0"],
+            &["exat", "-5"],
+        ] {
+            let err = parse_set(invalid).await.unwrap_err();
+            assert!(matches!(err, RespError::InvalidExpireTime("set")));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_bitfield() {
+        let args = [
+            "BITFIELD", "k", "get", "u8", "#2", "OVERFLOW", "FAIL", "INCRBY", "i16", "3", "-5",
+        ];
+        // a trailing op without its arguments
+        let err = parse_command(&encode(&[&args[..], &["GET"]].concat()), &Limits::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RespError::Syntax));
+
+        let input = encode(&args);
+        match parse_command(&input, &Limits::default()).await.unwrap() {
+            Command::BitField(key, ops) => {
+                assert_eq!(key, "k");
+                let u8 = FieldType {
+                    signed: false,
+                    bits: 8,
+                };
+                let i16 = FieldType {
+                    signed: true,
+                    bits: 16,
+                };
+                assert_eq!(
+                    ops,
+                    [
+                        bitfield::Op::Get(u8, 16),
+                        bitfield::Op::IncrBy(i16, 3, -5, Overflow::Fail),
+                    ]
+                );
+            }
+            other => panic!("expected BITFIELD, got {:?}", other),
+        }
+
+        assert!(matches!(
+            parse_field_type("u64"),
+            Err(RespError::BitfieldType)
+        ));
+        assert!(matches!(
+            parse_field_type("i0"),
+            Err(RespError::BitfieldType)
+        ));
+        assert!(parse_field_type("I64").is_ok());
+        let u8 = parse_field_type("u8").unwrap();
+        assert!(matches!(
+            parse_bit_offset("-1", u8),
+            Err(RespError::BitOffset)
+        ));
+        assert!(matches!(
+            parse_bit_offset("4294967289", u8),
+            Err(RespError::BitOffset)
+        ));
+        // offsets whose end, or whose `#` product, doesn't fit in a u64
+        for offset in [
+            "18446744073709551615",
+            "#2305843009213693951",
+            "#18446744073709551615",
+        ] {
+            let err = parse_args(&["BITFIELD", "k", "GET", "u8", offset])
+                .await
+                .unwrap_err();
+            assert!(matches!(err, RespError::BitOffset), "{}", offset);
+        }
+    }
+}
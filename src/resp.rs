@@ -0,0 +1,25 @@
+// RESP reply encoding
+
+// `$<len>\r\n<payload>\r\n`, or the null bulk string `$-1\r\n` for None
+pub fn encode_bulk_string(payload: Option<&[u8]>) -> Vec<u8> {
+    let Some(payload) = payload else {
+        return b"$-1\r\n".to_vec();
+    };
+    let mut out = format!("${}\r\n", payload.len()).into_bytes();
+    out.extend_from_slice(payload);
+    out.extend_from_slice(b"\r\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_bulk_string() {
+        assert_eq!(encode_bulk_string(Some(b"bar")), b"$3\r\nbar\r\n");
+        assert_eq!(encode_bulk_string(Some(b"")), b"$0\r\n\r\n");
+        assert_eq!(encode_bulk_string(Some(b"a\r\nb")), b"$4\r\na\r\nb\r\n");
+        assert_eq!(encode_bulk_string(None), b"$-1\r\n");
+    }
+}
@@ -1,231 +1,2763 @@
-use std::collections::HashMap;
-use std::env::args;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::bitfield;
+use crate::client::Clients;
+use crate::command::{ExpireCondition, SetCondition, SetOptions};
+use crate::config::Config;
+use crate::error::RespError;
+use crate::glob::glob_match;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::mem::size_of;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Error, ErrorKind, Read};
 
-#[derive(Debug)]
-pub struct Config {
-    dir: Option<String>,
-    dbfilename: Option<String>,
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    Set(HashSet<Vec<u8>>),
+    ZSet(HashMap<Vec<u8>, f64>),
 }
 
-#[derive(Clone)]
-struct ExpiringValue {
-    value: String,
-    expires_at: Option<SystemTime>,
-}
+impl Value {
+    // the name TYPE reports
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+        }
+    }
 
-pub struct Database {
-    config: Config,
-    db: RwLock<HashMap<String, ExpiringValue>>,
-}
+    // exhaustive on purpose: a new type can't compile without naming its
+    // encoding here, so DEBUG OBJECT never needs an "unknown" fallback
+    fn encoding(&self) -> &'static str {
+        match self {
+            Value::Str(s) if as_rdb_int(s).is_some() => "int",
+            Value::Str(s) if s.len() <= 44 => "embstr",
+            Value::Str(_) => "raw",
+            Value::List(_) => "quicklist",
+            Value::Hash(_) => "hashtable",
+            Value::Set(_) => "hashtable",
+            Value::ZSet(_) => "skiplist",
+        }
+    }
 
-impl Config {
-    pub fn new() -> Self {
-        Config {
-            dir: None,
-            dbfilename: None,
+    // the RDB type byte matching `rdb_encode`, which always writes the plain
+    // (non-listpack) encodings
+    #[cfg(test)] // only the loader tests write dumps so far
+    fn rdb_type(&self) -> u8 {
+        match self {
+            Value::Str(_) => 0,
+            Value::List(_) => 1,
+            Value::Set(_) => 2,
+            Value::Hash(_) => 4,
+            Value::ZSet(_) => 5,
         }
     }
 
-    pub fn from_args(&mut self) {
-        let args: Vec<String> = args().collect();
-        let mut iter = args.iter();
-        while let Some(arg) = iter.next() {
-            match arg.to_lowercase().as_str() {
-                "--dir" => {
-                    self.dir = iter.next().map(|s| s.to_owned());
+    // the RDB encoding of the value without its type byte, which is also
+    // what DUMP would carry ahead of its version and checksum footer
+    fn rdb_encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Value::Str(s) => rdb_write_string(&mut out, s),
+            Value::List(list) => {
+                rdb_write_length(&mut out, list.len());
+                for item in list {
+                    rdb_write_string(&mut out, item);
+                }
+            }
+            Value::Hash(hash) => {
+                rdb_write_length(&mut out, hash.len());
+                for (field, value) in hash {
+                    rdb_write_string(&mut out, field);
+                    rdb_write_string(&mut out, value);
+                }
+            }
+            Value::Set(set) => {
+                rdb_write_length(&mut out, set.len());
+                for member in set {
+                    rdb_write_string(&mut out, member);
                 }
-                "--dbfilename" => {
-                    self.dbfilename = iter.next().map(|s| s.to_owned());
+            }
+            Value::ZSet(zset) => {
+                rdb_write_length(&mut out, zset.len());
+                for (member, score) in zset {
+                    rdb_write_string(&mut out, member);
+                    out.extend_from_slice(&score.to_le_bytes());
                 }
-                _ => {}
             }
         }
+        out
     }
 
-    pub fn get(&self, key: &str) -> Option<String> {
-        match key.to_lowercase().as_str() {
-            "dir" => self.dir.clone(),
-            "dbfilename" => self.dbfilename.clone(),
-            _ => None,
+    // bytes held by the payload, counting each element's buffer and header;
+    // containers are extrapolated from their first `samples` elements, and
+    // 0 counts every element
+    fn memory_usage(&self, samples: usize) -> usize {
+        let elem = size_of::<Vec<u8>>();
+        match self {
+            Value::Str(s) => s.len(),
+            Value::List(list) => extrapolate(list.iter().map(|v| elem + v.len()), samples),
+            Value::Hash(hash) => extrapolate(
+                hash.iter().map(|(k, v)| 2 * elem + k.len() + v.len()),
+                samples,
+            ),
+            Value::Set(set) => extrapolate(set.iter().map(|v| elem + v.len()), samples),
+            Value::ZSet(zset) => extrapolate(
+                zset.keys().map(|k| elem + k.len() + size_of::<f64>()),
+                samples,
+            ),
         }
     }
+}
+
+fn extrapolate(sizes: impl ExactSizeIterator<Item = usize>, samples: usize) -> usize {
+    let len = sizes.len();
+    if samples == 0 || samples >= len {
+        return sizes.sum();
+    }
+    sizes.take(samples).sum::<usize>() * len / samples
+}
 
-    pub fn get_file_path(&self) -> Option<String> {
-        match (&self.dir, &self.dbfilename) {
-            (Some(dir), Some(dbfilename)) => Some(format!("{}/{}", dir, dbfilename)),
-            _ => None,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expiry {
+    // relative TTLs (EX/PX) run on the monotonic clock so wall-clock jumps
+    // can't stretch or cut them short
+    Deadline(Instant),
+    // absolute unix times (EXAT/PXAT), and everything loaded from an RDB file
+    At(SystemTime),
+}
+
+// both clocks read once, so a batch of expiry checks agrees on "now"
+#[derive(Debug, Clone, Copy)]
+struct Clock {
+    wall: SystemTime,
+    mono: Instant,
+}
+
+impl Clock {
+    fn now() -> Self {
+        Clock {
+            wall: SystemTime::now(),
+            mono: Instant::now(),
         }
     }
 }
-impl Database {
-    pub fn new() -> Self {
-        let mut config = Config::new();
-        config.from_args();
-        let db = match config.get_file_path() {
-            Some(file_path) => {
-                if let Some(file) = File::open(file_path).ok() {
-                    println!("reading from file");
-                    serialize(file)
-                } else {
-                    HashMap::new()
-                }
-            }
-            None => HashMap::new(),
+
+// TTL reports whole seconds rounded up, so 1500ms left is 2 and 1ms is 1
+fn ttl_secs(remaining_ms: u64) -> u64 {
+    remaining_ms.div_ceil(1000)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtlResult {
+    Missing,
+    NoExpiry,
+    Remaining(u64),
+}
+
+impl TtlResult {
+    // the integer TTL (seconds) and PTTL (milliseconds) reply with
+    pub fn reply(self, in_ms: bool) -> i64 {
+        match self {
+            TtlResult::Missing => -2,
+            TtlResult::NoExpiry => -1,
+            TtlResult::Remaining(ms) if in_ms => ms as i64,
+            TtlResult::Remaining(ms) => ttl_secs(ms) as i64,
+        }
+    }
+}
+
+impl Expiry {
+    // None when the deadline lies beyond what the clocks can represent
+    pub fn after(ttl: Duration) -> Option<Self> {
+        Instant::now().checked_add(ttl).map(Expiry::Deadline)
+    }
+
+    pub fn at_unix(since_epoch: Duration) -> Option<Self> {
+        UNIX_EPOCH.checked_add(since_epoch).map(Expiry::At)
+    }
+
+    fn remaining_ms(&self, now: Clock) -> u64 {
+        let remaining = match *self {
+            Expiry::Deadline(deadline) => deadline.saturating_duration_since(now.mono),
+            Expiry::At(at) => at.duration_since(now.wall).unwrap_or_default(),
         };
+        remaining.as_millis() as u64
+    }
+
+    fn is_past(&self, now: Clock) -> bool {
+        match *self {
+            Expiry::Deadline(deadline) => deadline < now.mono,
+            Expiry::At(at) => at < now.wall,
+        }
+    }
+}
+
+struct ExpiringValue {
+    value: Value,
+    expires_at: Option<Expiry>,
+    // milliseconds since UNIX_EPOCH, atomic so reads can touch it under the read lock
+    last_access: AtomicU64,
+}
+
+impl ExpiringValue {
+    fn new(value: Value, expires_at: Option<Expiry>) -> Self {
+        ExpiringValue {
+            value,
+            expires_at,
+            last_access: AtomicU64::new(now_ms()),
+        }
+    }
+
+    fn is_expired(&self, now: Clock) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at.is_past(now))
+    }
+
+    // rough bytes held for this entry: the key and value buffers plus the
+    // fixed overhead of the entry itself
+    fn memory_usage(&self, key: &str) -> usize {
+        self.sampled_memory_usage(key, 0)
+    }
+
+    fn sampled_memory_usage(&self, key: &str, samples: usize) -> usize {
+        key.len()
+            + size_of::<String>()
+            + self.value.memory_usage(samples)
+            + size_of::<ExpiringValue>()
+    }
+
+    fn touch(&self) {
+        self.last_access.store(now_ms(), Ordering::Relaxed);
+    }
+}
+
+impl Clone for ExpiringValue {
+    fn clone(&self) -> Self {
+        ExpiringValue {
+            value: self.value.clone(),
+            expires_at: self.expires_at,
+            last_access: AtomicU64::new(self.last_access.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+const KEYS_BATCH_SIZE: usize = 1024;
+
+// what one SET did; `old` is only filled in for SET ... GET
+#[derive(Debug, PartialEq)]
+pub struct SetOutcome {
+    pub written: bool,
+    pub old: Option<Vec<u8>>,
+}
+
+pub struct DebugObject {
+    pub encoding: &'static str,
+    pub serialized_length: usize,
+    pub lru: u64,
+    pub lru_seconds_idle: u64,
+}
+
+// the key map plus a running total of its memory_usage estimates, so
+// maxmemory checks don't walk every key, and the keys ordered by scan_hash, so
+// SCAN can resume from a cursor without walking them either. All writes go
+// through the methods below to keep both in step
+#[derive(Default)]
+struct Keyspace {
+    map: HashMap<String, ExpiringValue>,
+    used_memory: usize,
+    // keys sharing a hash are grouped, so a cursor never splits them
+    by_hash: BTreeMap<u64, Vec<String>>,
+}
+
+impl Deref for Keyspace {
+    type Target = HashMap<String, ExpiringValue>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+fn index_key(by_hash: &mut BTreeMap<u64, Vec<String>>, key: &str) {
+    by_hash
+        .entry(scan_hash(key))
+        .or_default()
+        .push(key.to_owned());
+}
+
+fn unindex_key(by_hash: &mut BTreeMap<u64, Vec<String>>, key: &str) {
+    let hash = scan_hash(key);
+    if let Some(group) = by_hash.get_mut(&hash) {
+        group.retain(|k| k != key);
+        if group.is_empty() {
+            by_hash.remove(&hash);
+        }
+    }
+}
+
+impl Keyspace {
+    fn new(map: HashMap<String, ExpiringValue>) -> Self {
+        let used_memory = map.iter().map(|(key, value)| value.memory_usage(key)).sum();
+        let mut by_hash = BTreeMap::new();
+        for key in map.keys() {
+            index_key(&mut by_hash, key);
+        }
+        Keyspace {
+            map,
+            used_memory,
+            by_hash,
+        }
+    }
+
+    fn insert(&mut self, key: String, value: ExpiringValue) -> Option<ExpiringValue> {
+        self.used_memory += value.memory_usage(&key);
+        let old = self.map.remove(&key);
+        match &old {
+            Some(old) => self.used_memory -= old.memory_usage(&key),
+            None => index_key(&mut self.by_hash, &key),
+        }
+        self.map.insert(key, value);
+        old
+    }
+
+    fn remove(&mut self, key: &str) -> Option<ExpiringValue> {
+        let old = self.map.remove(key)?;
+        self.used_memory -= old.memory_usage(key);
+        unindex_key(&mut self.by_hash, key);
+        Some(old)
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&String, &ExpiringValue) -> bool) {
+        let Keyspace {
+            map,
+            used_memory,
+            by_hash,
+        } = self;
+        map.retain(|key, value| {
+            let kept = keep(key, value);
+            if !kept {
+                *used_memory -= value.memory_usage(key);
+                unindex_key(by_hash, key);
+            }
+            kept
+        });
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.by_hash.clear();
+        self.used_memory = 0;
+    }
+
+    // the expiry isn't part of the estimate, so it can change in place
+    fn expiry_mut(&mut self, key: &str) -> Option<&mut Option<Expiry>> {
+        self.map.get_mut(key).map(|value| &mut value.expires_at)
+    }
+
+    // runs `f` on the entry and re-counts it afterwards
+    fn update<T>(&mut self, key: &str, f: impl FnOnce(&mut ExpiringValue) -> T) -> Option<T> {
+        let value = self.map.get_mut(key)?;
+        let before = value.memory_usage(key);
+        let result = f(value);
+        self.used_memory = self.used_memory - before + value.memory_usage(key);
+        Some(result)
+    }
+
+    // at least `count` keys (fewer at the end) whose hash is `cursor` or
+    // above, in hash order and whole hash groups at a time, plus the cursor
+    // that follows them: 0 once nothing is left
+    fn page(&self, cursor: u64, count: usize) -> (u64, Vec<&String>) {
+        let mut keys = Vec::new();
+        let mut groups = self.by_hash.range(cursor..);
+        while let Some((&hash, group)) = groups.next() {
+            keys.extend(group);
+            if keys.len() >= count {
+                // a group after this one has a larger hash, so this can't overflow
+                let next = groups.next().map_or(0, |_| hash + 1);
+                return (next, keys);
+            }
+        }
+        (0, keys)
+    }
+}
+
+pub struct Database {
+    config: Config,
+    clients: Clients,
+    db: RwLock<Keyspace>,
+    dirty: AtomicU64,
+    // there is no SAVE yet, so the dataset was last in sync with disk at startup
+    last_save: SystemTime,
+    // set while a background RDB load is running
+    loading: AtomicBool,
+}
+
+fn expire_condition_holds(
+    condition: ExpireCondition,
+    current: Option<Expiry>,
+    new: Expiry,
+    now: Clock,
+) -> bool {
+    let new = new.remaining_ms(now);
+    let current = current.map(|current| current.remaining_ms(now));
+    match condition {
+        ExpireCondition::Always => true,
+        ExpireCondition::Nx => current.is_none(),
+        ExpireCondition::Xx => current.is_some(),
+        ExpireCondition::Gt => current.is_some_and(|current| new > current),
+        ExpireCondition::Lt => current.is_none_or(|current| new < current),
+        ExpireCondition::XxLt => current.is_some_and(|current| new < current),
+    }
+}
+
+// SCAN's iteration order; the hasher is unseeded, so a key's position is the
+// same on every call
+fn scan_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+// the largest dump that is loaded before the first client is accepted; a
+// bigger one loads in the background while clients get -LOADING
+const BACKGROUND_LOAD_BYTES: u64 = 1024 * 1024;
+
+// db 0 of the configured dump; only db 0 is served, other databases in the
+// dump are dropped
+fn load_rdb(config: &Config) -> Result<HashMap<String, ExpiringValue>, Error> {
+    let Some(file) = config
+        .get_file_path()
+        .and_then(|path| File::open(path).ok())
+    else {
+        return Ok(HashMap::new());
+    };
+    let mut dbs = serialize(file)?;
+    for (index, keys) in dbs.range(1..) {
+        println!("rdb: skipping {} keys in db {}", keys.len(), index);
+    }
+    let keys = dbs.remove(&0).unwrap_or_default();
+    println!("rdb: loaded {} keys", keys.len());
+    Ok(keys)
+}
+
+// like redis, a dump that can't be read stops the server rather than leaving
+// it to serve an empty dataset in its place
+fn load_rdb_or_exit(config: &Config) -> HashMap<String, ExpiringValue> {
+    load_rdb(config).unwrap_or_else(|err| {
+        println!("{}, aborting", err);
+        std::process::exit(1);
+    })
+}
+
+// clears the loading flag however the background load ends, so a panic in
+// the loader can't leave every client getting -LOADING for good
+struct LoadingGuard<'a>(&'a AtomicBool);
+
+impl Drop for LoadingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl Database {
+    // loads the dump, if any, before returning
+    pub fn new(config: Config) -> Self {
+        let mut db = Database::empty(config);
+        *db.db.get_mut() = Keyspace::new(load_rdb_or_exit(&db.config));
+        db
+    }
 
+    fn empty(config: Config) -> Self {
         Database {
             config,
-            db: RwLock::new(db),
+            clients: Clients::new(),
+            db: RwLock::new(Keyspace::default()),
+            dirty: AtomicU64::new(0),
+            last_save: SystemTime::now(),
+            loading: AtomicBool::new(false),
+        }
+    }
+
+    // like `new`, but a large dump is loaded on a blocking thread so the
+    // server can answer (with -LOADING) in the meantime
+    pub fn open(config: Config) -> Arc<Self> {
+        let large = config
+            .get_file_path()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .is_some_and(|meta| meta.len() > BACKGROUND_LOAD_BYTES);
+        if !large {
+            return Arc::new(Database::new(config));
         }
+
+        let db = Arc::new(Database::empty(config));
+        db.loading.store(true, Ordering::Release);
+        let loader = Arc::clone(&db);
+        tokio::task::spawn_blocking(move || {
+            let _loading = LoadingGuard(&loader.loading);
+            let keys = load_rdb_or_exit(&loader.config);
+            *loader.db.blocking_write() = Keyspace::new(keys);
+        });
+        db
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::Acquire)
+    }
+
+    #[cfg(test)]
+    pub fn set_loading(&self, loading: bool) {
+        self.loading.store(loading, Ordering::Release);
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn clients(&self) -> &Clients {
+        &self.clients
+    }
+
+    // called by each write with the number of changes it actually made, so
+    // failed and no-op writes leave the count alone
+    fn mark_dirty(&self, changes: usize) {
+        self.dirty.fetch_add(changes as u64, Ordering::Relaxed);
     }
 
+    // changes since the last save
+    pub fn dirty(&self) -> u64 {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    // unix seconds
+    pub fn last_save(&self) -> u64 {
+        self.last_save
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+    }
+
+    // borrowed shorthands for the owned setter, handy when seeding tests
+    #[cfg(test)]
     pub async fn set(&self, key: &str, value: &str) {
-        let value = ExpiringValue {
-            value: value.to_owned(),
-            expires_at: None,
-        };
-        let mut db = self.db.write().await;
-        db.insert(key.to_owned(), value);
+        let (key, value) = (key.to_owned(), value.as_bytes().to_vec());
+        self.set_owned(key, value, SetOptions::default())
+            .await
+            .unwrap();
     }
 
+    #[cfg(test)]
     pub async fn set_with_expire(&self, key: &str, value: &str, expiry_in_ms: u64) {
-        let now = SystemTime::now();
-        let duration = Duration::from_millis(expiry_in_ms);
-        let value = ExpiringValue {
-            value: value.to_owned(),
-            expires_at: Some(now + duration),
+        let (key, value) = (key.to_owned(), value.as_bytes().to_vec());
+        let options = SetOptions {
+            expires_at: Expiry::after(Duration::from_millis(expiry_in_ms)),
+            ..SetOptions::default()
+        };
+        self.set_owned(key, value, options).await.unwrap();
+    }
+
+    // takes the parsed key and value by value so they move into the map as-is
+    pub async fn set_owned(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        options: SetOptions,
+    ) -> Result<SetOutcome, RespError> {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        let old = db.get(&key).filter(|value| !value.is_expired(now));
+        // SET ... GET refuses to replace a value it couldn't have replied with
+        let old_value = match old.map(|old| &old.value) {
+            Some(Value::Str(s)) if options.get => Some(s.clone()),
+            Some(_) if options.get => return Err(RespError::WrongType),
+            _ => None,
+        };
+        let written = match options.condition {
+            SetCondition::Nx => old.is_none(),
+            SetCondition::Xx => old.is_some(),
+            SetCondition::Always => true,
         };
+        if written {
+            let expires_at = match old {
+                Some(old) if options.keep_ttl => old.expires_at,
+                _ => options.expires_at,
+            };
+            db.insert(key, ExpiringValue::new(Value::Str(value), expires_at));
+            self.mark_dirty(1);
+        }
+        Ok(SetOutcome {
+            written,
+            old: old_value,
+        })
+    }
+
+    // all pairs go in under one write lock, so no reader sees half of them;
+    // like SET, each overwrite drops the key's old expiry
+    pub async fn mset(&self, pairs: Vec<(String, Vec<u8>)>) {
         let mut db = self.db.write().await;
-        db.insert(key.to_owned(), value);
+        self.mark_dirty(pairs.len());
+        for (key, value) in pairs {
+            db.insert(key, ExpiringValue::new(Value::Str(value), None));
+        }
     }
 
-    pub async fn get(&self, key: &str) -> Option<String> {
-        let now = SystemTime::now();
+    #[cfg(test)]
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, RespError> {
+        self.get_with(key, true).await
+    }
+
+    // `touch` is off for CLIENT NO-TOUCH connections, whose reads leave the
+    // idle time alone
+    pub async fn get_with(&self, key: &str, touch: bool) -> Result<Option<Vec<u8>>, RespError> {
+        let now = Clock::now();
 
-        let value = {
+        {
             let db = self.db.read().await;
-            db.get(key).cloned()
-        };
-        match value {
-            Some(v) => match v.expires_at {
-                Some(expires_at) if expires_at < now => {
-                    println!("now: {:?}, expires_at: {:?}", now, expires_at);
-                    let mut db = self.db.write().await;
-                    db.remove(key);
-                    None
+            match db.get(key) {
+                None => return Ok(None),
+                Some(v) if v.is_expired(now) => {}
+                Some(v) => {
+                    if touch {
+                        v.touch();
+                    }
+                    return match &v.value {
+                        Value::Str(s) => Ok(Some(s.clone())),
+                        _ => Err(RespError::WrongType),
+                    };
                 }
-                _ => Some(v.value),
+            }
+        }
+
+        let mut db = self.db.write().await;
+        // the key may have been overwritten since the read lock was released
+        if db.get(key).is_some_and(|v| v.is_expired(now)) {
+            db.remove(key);
+        }
+        Ok(None)
+    }
+
+    // runs the ops in order on one snapshot of the string; the key is only
+    // created when there is at least one write among them
+    pub async fn bitfield(
+        &self,
+        key: &str,
+        ops: &[bitfield::Op],
+    ) -> Result<Vec<Option<i64>>, RespError> {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        if db.get(key).is_some_and(|value| value.is_expired(now)) {
+            db.remove(key);
+        }
+        if !db.contains_key(key) && !ops.iter().any(|op| op.is_write()) {
+            let mut empty = Vec::new();
+            return Ok(ops
+                .iter()
+                .map(|op| bitfield::apply(&mut empty, *op))
+                .collect());
+        }
+        if !db.contains_key(key) {
+            db.insert(
+                key.to_owned(),
+                ExpiringValue::new(Value::Str(Vec::new()), None),
+            );
+        }
+        let results = db
+            .update(key, |entry| {
+                let Value::Str(bytes) = &mut entry.value else {
+                    return Err(RespError::WrongType);
+                };
+                let results: Vec<_> = ops.iter().map(|op| bitfield::apply(bytes, *op)).collect();
+                entry.touch();
+                Ok(results)
+            })
+            .unwrap()?;
+        // writes refused by OVERFLOW FAIL change nothing
+        let writes = ops.iter().zip(&results);
+        self.mark_dirty(
+            writes
+                .filter(|(op, r)| op.is_write() && r.is_some())
+                .count(),
+        );
+        Ok(results)
+    }
+
+    pub async fn strlen(&self, key: &str) -> Result<usize, RespError> {
+        let now = Clock::now();
+        let db = self.db.read().await;
+        match db.get(key).filter(|value| !value.is_expired(now)) {
+            None => Ok(0),
+            Some(value) => match &value.value {
+                Value::Str(s) => Ok(s.len()),
+                _ => Err(RespError::WrongType),
             },
-            None => None,
         }
     }
 
+    // a missing key counts as 0; the key keeps whatever TTL it had
+    pub async fn incr_by(&self, key: &str, delta: i64) -> Result<i64, RespError> {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        if db.get(key).is_some_and(|value| value.is_expired(now)) {
+            db.remove(key);
+        }
+        if !db.contains_key(key) {
+            db.insert(
+                key.to_owned(),
+                ExpiringValue::new(Value::Str(b"0".to_vec()), None),
+            );
+        }
+        let n = db
+            .update(key, |entry| {
+                let Value::Str(current) = &mut entry.value else {
+                    return Err(RespError::WrongType);
+                };
+                let n: i64 = std::str::from_utf8(current)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or(RespError::NotInteger)?;
+                let n = n.checked_add(delta).ok_or(RespError::Overflow)?;
+                *current = n.to_string().into_bytes();
+                entry.touch();
+                Ok(n)
+            })
+            .unwrap()?;
+        self.mark_dirty(1);
+        Ok(n)
+    }
+
+    // creates the key when missing; the key keeps whatever TTL it had
+    pub async fn append(&self, key: &str, value: &[u8]) -> Result<usize, RespError> {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        if db.get(key).is_some_and(|value| value.is_expired(now)) {
+            db.remove(key);
+        }
+        if !db.contains_key(key) {
+            db.insert(
+                key.to_owned(),
+                ExpiringValue::new(Value::Str(Vec::new()), None),
+            );
+        }
+        let len = db
+            .update(key, |entry| {
+                let Value::Str(current) = &mut entry.value else {
+                    return Err(RespError::WrongType);
+                };
+                current.extend_from_slice(value);
+                let len = current.len();
+                entry.touch();
+                Ok(len)
+            })
+            .unwrap()?;
+        self.mark_dirty(1);
+        Ok(len)
+    }
+
+    pub async fn del(&self, keys: &[String]) -> usize {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        let deleted = keys
+            .iter()
+            .filter_map(|key| db.remove(key))
+            // an expired key that wasn't purged yet is already gone for clients
+            .filter(|value| !value.is_expired(now))
+            .count();
+        self.mark_dirty(deleted);
+        deleted
+    }
+
+    // repeated keys are counted each time they appear, like redis does
+    pub async fn exists(&self, keys: &[String]) -> usize {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        let mut count = 0;
+        for key in keys {
+            match db.get(key) {
+                None => {}
+                Some(value) if value.is_expired(now) => {
+                    db.remove(key);
+                }
+                Some(_) => count += 1,
+            }
+        }
+        count
+    }
+
+    // one read lock for the whole batch; missing, expired and non-string keys
+    // all read as nil
+    pub async fn mget(&self, keys: &[String], touch: bool) -> Vec<Option<Vec<u8>>> {
+        let now = Clock::now();
+        let db = self.db.read().await;
+        keys.iter()
+            .map(|key| {
+                let value = db.get(key).filter(|value| !value.is_expired(now))?;
+                if touch {
+                    value.touch();
+                }
+                match &value.value {
+                    Value::Str(s) => Some(s.clone()),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    // walks the keyspace a SCAN page at a time, releasing the lock between
+    // pages so writers can get in; expired keys met along the way are purged.
+    // Every key present for the whole call is returned exactly once
     pub async fn keys(&self, pattern: &str) -> Vec<String> {
-        let now = SystemTime::now();
-        let mut expired_keys = Vec::new();
         let mut valid_keys = Vec::new();
-
-        {
-            let db = self.db.read().await;
-            for (key, value) in db.iter() {
-                match value.expires_at {
-                    Some(expires_at) if expires_at < now => {
-                        expired_keys.push(key.to_owned());
+        let mut cursor = 0;
+        loop {
+            let now = Clock::now();
+            let mut expired_keys = Vec::new();
+            let next = {
+                let db = self.db.read().await;
+                let (next, page) = db.page(cursor, KEYS_BATCH_SIZE);
+                for key in page {
+                    if !glob_match(pattern.as_bytes(), key.as_bytes()) {
+                        continue;
                     }
-                    _ => {
-                        valid_keys.push(key.to_owned());
+                    if db[key.as_str()].is_expired(now) {
+                        expired_keys.push(key.clone());
+                    } else {
+                        valid_keys.push(key.clone());
+                    }
+                }
+                next
+            };
+
+            if !expired_keys.is_empty() {
+                let mut db = self.db.write().await;
+                for key in expired_keys {
+                    // the key may have been overwritten since the read lock was released
+                    if db.get(&key).is_some_and(|value| value.is_expired(now)) {
+                        db.remove(&key);
                     }
                 }
             }
-        }
 
-        {
-            let mut db = self.db.write().await;
-            for key in expired_keys {
-                db.remove(&key);
+            if next == 0 {
+                return valid_keys;
             }
+            cursor = next;
         }
-
-        valid_keys
     }
 
-    pub async fn config_get(&self, key: &str) -> Option<String> {
-        self.config.get(key)
+    // the number of live keys; expired ones found along the way are dropped
+    pub async fn dbsize(&self) -> usize {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        db.retain(|_, value| !value.is_expired(now));
+        db.len()
     }
-}
 
-fn length_encode(buf: &[u8]) -> Option<(usize, usize)> {
-    let mask = 3u8 << 6; // 1100 0000
-    let num = match buf[0] & mask {
-        0 => (u32::from_be_bytes([0, 0, 0, buf[0]]), 1),
-        64u8 => (u32::from_be_bytes([0, 0, buf[0] & (64u8 - 1), buf[1]]), 2),
-        128u8 => (u32::from_be_bytes(buf[1..5].try_into().unwrap()), 5),
-        192u8 => return None,
-        _ => unreachable!(),
-    };
-    let num = (num.0 as usize, num.1);
-    Some(num)
-}
-
-fn serialize_kv(buf: &[u8]) -> Option<(String, ExpiringValue, usize)> {
-    let is_expired = buf[0] == 0xfc;
-    let expires_at = if is_expired {
-        let expires_at = u64::from_le_bytes(buf[1..9].try_into().unwrap());
-        Some(UNIX_EPOCH + Duration::from_millis(expires_at as u64))
-    } else {
-        None
-    };
-    let mut pos = if is_expired { 10 } else { 1 };
-
-    let (key_len, offset) = length_encode(&buf[pos..]).unwrap();
-    pos += offset;
-    let key = String::from_utf8(buf[pos..pos + key_len].to_vec()).unwrap();
-    pos += key_len;
+    pub async fn flush(&self) {
+        let mut db = self.db.write().await;
+        self.mark_dirty(db.len());
+        db.clear();
+    }
 
-    let (value_len, offset) = length_encode(&buf[pos..]).unwrap();
-    pos += offset;
-    let value = String::from_utf8(buf[pos..pos + value_len].to_vec()).unwrap();
+    // one SCAN step over the keys in order of a fixed hash of their names,
+    // with the cursor as the hash to resume from. That position doesn't shift
+    // when keys before it come or go, so every key present for the whole
+    // iteration is returned exactly once; keys added or removed meanwhile may
+    // or may not be. Keys sharing a hash always come back in the same page.
+    // MATCH and TYPE filter a page after it is taken, so a page may come back
+    // short or empty. A returned cursor of 0 ends the iteration. The keys are
+    // kept in hash order, so a step costs O(log N + COUNT), not a map walk
+    pub async fn scan(
+        &self,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&str>,
+        type_filter: Option<&str>,
+    ) -> (u64, Vec<String>) {
+        let now = Clock::now();
+        let db = self.db.read().await;
+        let (next, page) = db.page(cursor, count);
+        let keys = page
+            .into_iter()
+            .filter(|key| pattern.is_none_or(|p| glob_match(p.as_bytes(), key.as_bytes())))
+            .filter(|key| {
+                let value = &db[key.as_str()];
+                !value.is_expired(now)
+                    && type_filter.is_none_or(|t| t.eq_ignore_ascii_case(value.value.kind()))
+            })
+            .cloned()
+            .collect();
+        (next, keys)
+    }
 
-    let value = ExpiringValue {
-        value,
-        expires_at: expires_at,
-    };
-    Some((key, value, pos + value_len))
-}
+    pub async fn ttl(&self, key: &str) -> TtlResult {
+        let now = Clock::now();
+        let db = self.db.read().await;
+        match db.get(key).filter(|value| !value.is_expired(now)) {
+            None => TtlResult::Missing,
+            Some(value) => match value.expires_at {
+                None => TtlResult::NoExpiry,
+                Some(expires_at) => TtlResult::Remaining(expires_at.remaining_ms(now)),
+            },
+        }
+    }
 
-fn serialize(file: File) -> HashMap<String, ExpiringValue> {
-    let now = SystemTime::now();
-    println!("now: {:?}", now);
-    let mut reader = BufReader::new(file);
-    let mut buf = [0u8; 1024];
-    let bytes_read = reader.read(&mut buf).unwrap();
+    // false for a missing key or when the condition refused the change; an
+    // expiry that is already due deletes the key
+    pub async fn set_expiry(
+        &self,
+        key: &str,
+        expires_at: Expiry,
+        condition: ExpireCondition,
+    ) -> bool {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        match db.get(key) {
+            None => false,
+            Some(value) if value.is_expired(now) => {
+                db.remove(key);
+                false
+            }
+            Some(value)
+                if !expire_condition_holds(condition, value.expires_at, expires_at, now) =>
+            {
+                false
+            }
+            Some(_) if expires_at.is_past(now) => {
+                db.remove(key);
+                self.mark_dirty(1);
+                true
+            }
+            Some(_) => {
+                *db.expiry_mut(key).unwrap() = Some(expires_at);
+                self.mark_dirty(1);
+                true
+            }
+        }
+    }
 
-    let fb_pos = buf.iter().position(|&b| b == 0xfb).unwrap();
-    let mut pos = fb_pos + 1;
-    let (hashtable_size, offset) = length_encode(&buf[pos..]).unwrap();
-    pos += offset;
-    let (exprie_hashtable_size, offset) = length_encode(&buf[pos..]).unwrap();
-    pos += offset;
+    // moves the value and its expiry under one write lock; with `nx` an
+    // existing destination is left alone and Ok(false) is returned
+    pub async fn rename(&self, src: &str, dst: &str, nx: bool) -> Result<bool, RespError> {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        if db.get(src).is_none_or(|value| value.is_expired(now)) {
+            db.remove(src);
+            return Err(RespError::NoSuchKey);
+        }
+        let dst_exists = db.get(dst).is_some_and(|value| !value.is_expired(now));
+        if src == dst {
+            return Ok(!nx);
+        }
+        if nx && dst_exists {
+            return Ok(false);
+        }
+        let value = db.remove(src).unwrap();
+        db.insert(dst.to_owned(), value);
+        self.mark_dirty(1);
+        Ok(true)
+    }
 
-    let mut db = HashMap::new();
-    for _ in 0..hashtable_size {
-        let (key, value, offset) = serialize_kv(&buf[pos..]).unwrap();
-        match value.expires_at {
-            Some(expires_at) if expires_at < now => {
-                println!("key: {}, expires_at: {:?}", key, expires_at);
+    // true only when there was an expiry to drop
+    pub async fn persist(&self, key: &str) -> bool {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        match db.get(key) {
+            Some(value) if value.is_expired(now) => {
+                db.remove(key);
+                false
             }
-            _ => {
-                println!("key: {}, expires_at: {:?}", key, value.expires_at);
-                db.insert(key, value);
+            Some(_) => {
+                let persisted = db.expiry_mut(key).unwrap().take().is_some();
+                self.mark_dirty(persisted as usize);
+                persisted
             }
+            None => false,
         }
-        pos += offset;
     }
 
-    db
-}
\ No newline at end of file
+    // "none" for missing and expired keys
+    pub async fn key_type(&self, key: &str) -> &'static str {
+        let now = Clock::now();
+        let db = self.db.read().await;
+        db.get(key)
+            .filter(|value| !value.is_expired(now))
+            .map_or("none", |value| value.value.kind())
+    }
+
+    pub async fn debug_object(&self, key: &str) -> Option<DebugObject> {
+        let now = Clock::now();
+        let db = self.db.read().await;
+        let value = db.get(key).filter(|value| !value.is_expired(now))?;
+        let last_access = value.last_access.load(Ordering::Relaxed) / 1000;
+        Some(DebugObject {
+            encoding: value.value.encoding(),
+            serialized_length: value.value.rdb_encode().len(),
+            // redis keeps a 24-bit LRU clock in seconds
+            lru: last_access & 0xff_ffff,
+            lru_seconds_idle: (now_ms() / 1000).saturating_sub(last_access),
+        })
+    }
+
+    pub async fn idle_time(&self, key: &str) -> Option<u64> {
+        let now = Clock::now();
+        let db = self.db.read().await;
+        let value = db.get(key).filter(|value| !value.is_expired(now))?;
+        let idle_ms = now_ms().saturating_sub(value.last_access.load(Ordering::Relaxed));
+        Some(idle_ms / 1000)
+    }
+
+    // `samples` as in MEMORY USAGE ... SAMPLES: 0 walks the whole container
+    pub async fn memory_usage(&self, key: &str, samples: usize) -> Option<usize> {
+        let now = Clock::now();
+        let db = self.db.read().await;
+        db.get(key)
+            .filter(|value| !value.is_expired(now))
+            .map(|value| value.sampled_memory_usage(key, samples))
+    }
+
+    // the sum of every key's MEMORY USAGE estimate, kept up to date on writes
+    pub async fn used_memory(&self) -> usize {
+        self.db.read().await.used_memory
+    }
+
+    pub async fn over_maxmemory(&self) -> bool {
+        let maxmemory = self.config().maxmemory;
+        maxmemory > 0 && self.used_memory().await > maxmemory
+    }
+
+    // removes every key whose expiry has passed and returns how many were dropped
+    pub async fn purge_expired(&self) -> usize {
+        let now = Clock::now();
+        let mut db = self.db.write().await;
+        let before = db.len();
+        db.retain(|_, value| !value.is_expired(now));
+        before - db.len()
+    }
+
+    pub async fn config_get(&self, key: &str) -> Option<String> {
+        self.config.get(key)
+    }
+}
+
+fn rdb_write_length(out: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=0x3f => out.push(len as u8),
+        0x40..=0x3fff => out.extend_from_slice(&[0x40 | (len >> 8) as u8, len as u8]),
+        _ => {
+            out.push(0x80);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+// strings that round-trip through an i32 are stored in the integer encodings
+fn as_rdb_int(s: &[u8]) -> Option<i32> {
+    let n: i32 = std::str::from_utf8(s).ok()?.parse().ok()?;
+    (n.to_string().as_bytes() == s).then_some(n)
+}
+
+fn rdb_write_string(out: &mut Vec<u8>, s: &[u8]) {
+    match as_rdb_int(s) {
+        Some(n) if i8::try_from(n).is_ok() => out.extend_from_slice(&[0xc0, n as u8]),
+        Some(n) if i16::try_from(n).is_ok() => {
+            out.push(0xc1);
+            out.extend_from_slice(&(n as i16).to_le_bytes());
+        }
+        Some(n) => {
+            out.push(0xc2);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        None => {
+            rdb_write_length(out, s.len());
+            out.extend_from_slice(s);
+        }
+    }
+}
+
+// a little-endian two's complement integer of 1 to 8 bytes
+fn le_int(bytes: &[u8]) -> i64 {
+    let mut n = [0u8; 8];
+    n[..bytes.len()].copy_from_slice(bytes);
+    let shift = 64 - 8 * bytes.len() as u32;
+    (i64::from_le_bytes(n) << shift) >> shift
+}
+
+// the entries of a listpack blob, integers rendered as decimal strings
+fn listpack_entries(lp: &[u8]) -> Option<Vec<Vec<u8>>> {
+    // skip the 4-byte total size and 2-byte element count
+    let mut pos = 6;
+    let mut entries = Vec::new();
+    loop {
+        let b = *lp.get(pos)?;
+        let string = |start: usize, len: usize| lp.get(start..start + len).map(<[u8]>::to_vec);
+        let int = |n: i64| n.to_string().into_bytes();
+        let (entry, len) = match b {
+            0xff => return Some(entries),
+            0x00..=0x7f => (int(b as i64), 1),
+            0x80..=0xbf => {
+                let len = (b & 0x3f) as usize;
+                (string(pos + 1, len)?, 1 + len)
+            }
+            0xc0..=0xdf => {
+                let n = ((b as i64 & 0x1f) << 8) | *lp.get(pos + 1)? as i64;
+                // 13-bit two's complement
+                (int(if n >= 1 << 12 { n - (1 << 13) } else { n }), 2)
+            }
+            0xe0..=0xef => {
+                let len = ((b as usize & 0x0f) << 8) | *lp.get(pos + 1)? as usize;
+                (string(pos + 2, len)?, 2 + len)
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(lp.get(pos + 1..pos + 5)?.try_into().ok()?) as usize;
+                (string(pos + 5, len)?, 5 + len)
+            }
+            0xf1..=0xf4 => {
+                let width = [2, 3, 4, 8][(b - 0xf1) as usize];
+                (int(le_int(lp.get(pos + 1..pos + 1 + width)?)), 1 + width)
+            }
+            _ => return None,
+        };
+        // each entry is followed by its own length, 7 bits per backlen byte
+        let backlen = match len {
+            0..=127 => 1,
+            128..=16_383 => 2,
+            16_384..=2_097_151 => 3,
+            2_097_152..=268_435_455 => 4,
+            _ => 5,
+        };
+        entries.push(entry);
+        pos += len + backlen;
+    }
+}
+
+// the entries of a ziplist blob, the pre-7.0 predecessor of the listpack
+fn ziplist_entries(zl: &[u8]) -> Option<Vec<Vec<u8>>> {
+    // skip the 4-byte total size, 4-byte tail offset and 2-byte entry count
+    let mut pos = 10;
+    let mut entries = Vec::new();
+    loop {
+        // each entry starts with the previous entry's length, 1 or 5 bytes
+        match *zl.get(pos)? {
+            0xff => return Some(entries),
+            0xfe => pos += 5,
+            _ => pos += 1,
+        }
+        let b = *zl.get(pos)?;
+        let string = |start: usize, len: usize| zl.get(start..start + len).map(<[u8]>::to_vec);
+        let int = |width: usize| {
+            let n = le_int(zl.get(pos + 1..pos + 1 + width)?);
+            Some((n.to_string().into_bytes(), 1 + width))
+        };
+        let (entry, len) = match b >> 6 {
+            0 => {
+                let len = (b & 0x3f) as usize;
+                (string(pos + 1, len)?, 1 + len)
+            }
+            1 => {
+                let len = ((b as usize & 0x3f) << 8) | *zl.get(pos + 1)? as usize;
+                (string(pos + 2, len)?, 2 + len)
+            }
+            2 => {
+                let len = u32::from_be_bytes(zl.get(pos + 1..pos + 5)?.try_into().ok()?) as usize;
+                (string(pos + 5, len)?, 5 + len)
+            }
+            _ => match b {
+                0xc0 => int(2)?,
+                0xd0 => int(4)?,
+                0xe0 => int(8)?,
+                0xf0 => int(3)?,
+                0xfe => int(1)?,
+                // 4-bit immediates 1..=13 stand for 0..=12
+                0xf1..=0xfd => ((b as i64 - 0xf1).to_string().into_bytes(), 1),
+                _ => return None,
+            },
+        };
+        entries.push(entry);
+        pos += len;
+    }
+}
+
+fn intset_entries(blob: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let width = u32::from_le_bytes(blob.get(0..4)?.try_into().ok()?) as usize;
+    let len = u32::from_le_bytes(blob.get(4..8)?.try_into().ok()?) as usize;
+    if !matches!(width, 2 | 4 | 8) {
+        return None;
+    }
+    let ints = blob.get(8..8 + len * width)?;
+    Some(
+        ints.chunks(width)
+            .map(|n| le_int(n).to_string().into_bytes())
+            .collect(),
+    )
+}
+
+fn parse_score(score: &[u8]) -> Option<f64> {
+    std::str::from_utf8(score).ok()?.parse().ok()
+}
+
+// RDB fields read on demand from a buffered reader, so a dump is never held
+// in memory whole; every byte consumed is folded into the running checksum
+struct RdbReader<R> {
+    inner: BufReader<R>,
+    crc: u64,
+}
+
+impl<R: Read> RdbReader<R> {
+    fn new(inner: R) -> Self {
+        RdbReader {
+            inner: BufReader::new(inner),
+            crc: 0,
+        }
+    }
+
+    // None at end of input
+    fn bytes(&mut self, len: usize) -> Option<Vec<u8>> {
+        // `take` keeps a corrupt length from reserving the whole size up front
+        let mut out = Vec::new();
+        (&mut self.inner)
+            .take(len as u64)
+            .read_to_end(&mut out)
+            .ok()?;
+        if out.len() < len {
+            return None;
+        }
+        self.crc = crc64_update(self.crc, &out);
+        Some(out)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let mut out = [0; N];
+        self.inner.read_exact(&mut out).ok()?;
+        self.crc = crc64_update(self.crc, &out);
+        Some(out)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.array::<1>().map(|[b]| b)
+    }
+
+    // a length, or Err with the low bits of a special (11xxxxxx) encoding
+    fn length_or_special(&mut self) -> Option<Result<usize, u8>> {
+        let first = self.u8()?;
+        let len = match first >> 6 {
+            0 => first as usize,
+            1 => ((first as usize & 0x3f) << 8) | self.u8()? as usize,
+            2 => u32::from_be_bytes(self.array()?) as usize,
+            _ => return Some(Err(first & 0x3f)),
+        };
+        Some(Ok(len))
+    }
+
+    fn length(&mut self) -> Option<usize> {
+        self.length_or_special()?.ok()
+    }
+
+    // a length-prefixed string, or one of the integer encodings (0xC0..0xC2)
+    fn string(&mut self) -> Option<Vec<u8>> {
+        match self.length_or_special()? {
+            Ok(len) => self.bytes(len),
+            Err(special) => {
+                let len = match special {
+                    0 => 1,
+                    1 => 2,
+                    2 => 4,
+                    _ => return None,
+                };
+                let value = le_int(&self.bytes(len)?);
+                Some(value.to_string().into_bytes())
+            }
+        }
+    }
+
+    fn strings(&mut self, count: usize) -> Option<Vec<Vec<u8>>> {
+        (0..count).map(|_| self.string()).collect()
+    }
+}
+
+// the value types read_value understands
+fn is_supported_type(kind: u8) -> bool {
+    matches!(kind, 0..=5 | 11 | 14 | 16..=18 | 20)
+}
+
+// the value of a key entry of the given RDB type
+fn read_value(kind: u8, rdb: &mut RdbReader<impl Read>) -> Option<Value> {
+    let pairs = |items: Vec<Vec<u8>>| {
+        let mut items = items.into_iter();
+        std::iter::from_fn(move || Some((items.next()?, items.next()?)))
+    };
+    let value = match kind {
+        0 => Value::Str(rdb.string()?),
+        1 | 2 | 4 => {
+            let count = rdb.length()?;
+            let count = if kind == 4 {
+                count.checked_mul(2)?
+            } else {
+                count
+            };
+            let items = rdb.strings(count)?;
+            match kind {
+                1 => Value::List(items.into()),
+                2 => Value::Set(items.into_iter().collect()),
+                _ => Value::Hash(pairs(items).collect()),
+            }
+        }
+        // sorted sets with scores as length-prefixed strings (3) or binary doubles (5)
+        3 | 5 => {
+            let mut zset = HashMap::new();
+            for _ in 0..rdb.length()? {
+                let member = rdb.string()?;
+                let score = if kind == 5 {
+                    f64::from_le_bytes(rdb.array()?)
+                } else {
+                    match rdb.u8()? {
+                        253 => f64::NAN,
+                        254 => f64::INFINITY,
+                        255 => f64::NEG_INFINITY,
+                        len => parse_score(&rdb.bytes(len as usize)?)?,
+                    }
+                };
+                zset.insert(member, score);
+            }
+            Value::ZSet(zset)
+        }
+        11 => Value::Set(intset_entries(&rdb.string()?)?.into_iter().collect()),
+        16 | 17 | 20 => {
+            let entries = listpack_entries(&rdb.string()?)?;
+            match kind {
+                16 => Value::Hash(pairs(entries).collect()),
+                17 => {
+                    let scored = pairs(entries).map(|(m, s)| Some((m, parse_score(&s)?)));
+                    Value::ZSet(scored.collect::<Option<_>>()?)
+                }
+                _ => Value::Set(entries.into_iter().collect()),
+            }
+        }
+        // quicklist: every node is a ziplist
+        14 => {
+            let mut list = VecDeque::new();
+            for _ in 0..rdb.length()? {
+                list.extend(ziplist_entries(&rdb.string()?)?);
+            }
+            Value::List(list)
+        }
+        // quicklist 2: nodes that are either a plain element (1) or a listpack (2)
+        18 => {
+            let mut list = VecDeque::new();
+            for _ in 0..rdb.length()? {
+                let container = rdb.length()?;
+                let node = rdb.string()?;
+                match container {
+                    1 => list.push_back(node),
+                    2 => list.extend(listpack_entries(&node)?),
+                    _ => return None,
+                }
+            }
+            Value::List(list)
+        }
+        // ruled out by is_supported_type
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn rdb_error(msg: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("rdb: {}", msg))
+}
+
+// the reader's fields come back as None when the input ends mid-field or
+// uses an encoding we can't read
+fn short_read() -> Error {
+    rdb_error("short read or unreadable field")
+}
+
+// a key entry after its optional expiry opcode, which has already been read
+fn serialize_kv(
+    opcode: u8,
+    rdb: &mut RdbReader<impl Read>,
+) -> Result<(String, ExpiringValue), Error> {
+    let (expires_at, kind) = match opcode {
+        0xfc => {
+            let ms = u64::from_le_bytes(rdb.array().ok_or_else(short_read)?);
+            let kind = rdb.u8().ok_or_else(short_read)?;
+            (Some(UNIX_EPOCH + Duration::from_millis(ms)), kind)
+        }
+        0xfd => {
+            let secs = u32::from_le_bytes(rdb.array().ok_or_else(short_read)?);
+            let kind = rdb.u8().ok_or_else(short_read)?;
+            (Some(UNIX_EPOCH + Duration::from_secs(secs as u64)), kind)
+        }
+        kind => (None, kind),
+    };
+    if !is_supported_type(kind) {
+        return Err(rdb_error(format!("unsupported RDB type {}", kind)));
+    }
+    // keys are Strings; mapping a binary one lossily could merge it with another
+    let key = String::from_utf8(rdb.string().ok_or_else(short_read)?)
+        .map_err(|_| rdb_error("a key is not valid UTF-8"))?;
+    let value = read_value(kind, rdb)
+        .ok_or_else(|| rdb_error(format!("bad value of type {} for key {:?}", kind, key)))?;
+    Ok((key, ExpiringValue::new(value, expires_at.map(Expiry::At))))
+}
+
+// CRC-64/Jones as used by redis, reflected with no final xor
+fn crc64_update(mut crc: u64, data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac9329ac4bc9b5;
+    for &byte in data {
+        crc ^= byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+fn crc64(data: &[u8]) -> u64 {
+    crc64_update(0, data)
+}
+
+// `crc` covers everything up to and including the EOF opcode; a dump that
+// fails the check is treated like any other unreadable one
+fn verify_checksum(crc: u64, trailer: Option<[u8; 8]>) -> Result<(), Error> {
+    let trailer = trailer.ok_or_else(|| rdb_error("missing checksum after EOF"))?;
+    let expected = u64::from_le_bytes(trailer);
+    // a zero checksum means the dump was written with rdbchecksum off
+    if expected != 0 && crc != expected {
+        return Err(rdb_error("checksum mismatch, the file may be corrupt"));
+    }
+    Ok(())
+}
+
+// keys grouped by the database index selected with 0xFE, db 0 until one is seen
+fn serialize(file: impl Read) -> Result<BTreeMap<usize, HashMap<String, ExpiringValue>>, Error> {
+    let now = Clock::now();
+    let mut rdb = RdbReader::new(file);
+
+    let mut dbs = BTreeMap::new();
+    let mut db_index = 0;
+    // the "REDIS" magic and 4-digit version
+    let header = rdb.bytes(9).ok_or_else(short_read)?;
+    if !header.starts_with(b"REDIS") {
+        return Err(rdb_error("wrong signature"));
+    }
+    loop {
+        match rdb.u8() {
+            None => return Err(rdb_error("no EOF opcode, the file is truncated")),
+            // EOF: only the 8-byte checksum may follow, anything after it is ignored
+            Some(0xff) => {
+                let crc = rdb.crc;
+                verify_checksum(crc, rdb.array())?;
+                break;
+            }
+            // aux field: name and value strings, neither of which we keep
+            Some(0xfa) => {
+                rdb.string().ok_or_else(short_read)?;
+                rdb.string().ok_or_else(short_read)?;
+            }
+            Some(0xfe) => db_index = rdb.length().ok_or_else(short_read)?,
+            // resizedb: the counts are only hints, entries are read until EOF
+            Some(0xfb) => {
+                let hashtable_size = rdb.length().ok_or_else(short_read)?;
+                let _expire_hashtable_size = rdb.length().ok_or_else(short_read)?;
+                dbs.entry(db_index)
+                    .or_insert_with(HashMap::new)
+                    .reserve(hashtable_size);
+            }
+            Some(opcode) => {
+                let (key, value) = serialize_kv(opcode, &mut rdb)?;
+                // keys that expired while the dump sat on disk are dropped
+                if !value
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at.is_past(now))
+                {
+                    dbs.entry(db_index)
+                        .or_insert_with(HashMap::new)
+                        .insert(key, value);
+                }
+            }
+        }
+    }
+
+    Ok(dbs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    // hands out a single byte per read() call
+    struct TrickleReader<'a>(&'a [u8]);
+
+    impl Read for TrickleReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    // records the largest single read() the loader asked for
+    struct CountingReader<'a> {
+        data: &'a [u8],
+        largest_read: usize,
+    }
+
+    impl Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.largest_read = self.largest_read.max(buf.len());
+            self.data.read(buf)
+        }
+    }
+
+    fn rdb_fixture() -> Vec<u8> {
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.extend_from_slice(&[0xfa, 9]);
+        rdb.extend_from_slice(b"redis-ver");
+        rdb.extend_from_slice(&[5]);
+        rdb.extend_from_slice(b"7.2.0");
+        rdb.extend_from_slice(&[0xfa, 10]);
+        rdb.extend_from_slice(b"redis-bits");
+        rdb.extend_from_slice(&[0xc0, 0x40]);
+        rdb.extend_from_slice(&[0xfe, 0x00, 0xfb, 0x02, 0x00]);
+        rdb.extend_from_slice(&[0x00, 3]);
+        rdb.extend_from_slice(b"foo");
+        rdb.extend_from_slice(&[3]);
+        rdb.extend_from_slice(b"bar");
+        rdb.extend_from_slice(&[0x00, 5]);
+        rdb.extend_from_slice(b"hello");
+        rdb.extend_from_slice(&[5]);
+        rdb.extend_from_slice(b"world");
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+        rdb
+    }
+
+    #[test]
+    fn test_serialize_streams_large_dumps() {
+        let mut rdb = b"REDIS0011".to_vec();
+        let value = vec![b'v'; 1024];
+        for i in 0..4096 {
+            rdb_entry(&mut rdb, 0, &format!("k{}", i));
+            rdb_write_string(&mut rdb, &value);
+        }
+        rdb.push(0xff);
+        let crc = crc64(&rdb);
+        rdb.extend_from_slice(&crc.to_le_bytes());
+        assert!(rdb.len() > 4 * 1024 * 1024);
+
+        let mut reader = CountingReader {
+            data: &rdb,
+            largest_read: 0,
+        };
+        let db = serialize(&mut reader).unwrap().remove(&0).unwrap();
+        assert_eq!(db.len(), 4096);
+        assert_eq!(db["k4095"].value, Value::Str(value));
+        assert!(reader.data.is_empty());
+        // fed through BufReader-sized reads rather than one buffer for the file
+        assert!(reader.largest_read <= 64 * 1024);
+    }
+
+    #[test]
+    fn test_serialize_with_partial_reads() {
+        let rdb = rdb_fixture();
+        let db = &serialize(TrickleReader(&rdb)).unwrap()[&0];
+        assert_eq!(db.len(), 2);
+        assert_eq!(db["foo"].value, Value::Str(b"bar".to_vec()));
+        assert_eq!(db["hello"].value, Value::Str(b"world".to_vec()));
+    }
+
+    #[test]
+    fn test_loading_cleared_when_loader_panics() {
+        let loading = Arc::new(AtomicBool::new(true));
+        let flag = Arc::clone(&loading);
+        let loader = std::thread::spawn(move || {
+            let _loading = LoadingGuard(&flag);
+            panic!("corrupt dump");
+        });
+        assert!(loader.join().is_err());
+        assert!(!loading.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn test_serialize_rejects_truncated_dumps() {
+        let rdb = rdb_fixture();
+        // every cut before the EOF opcode, including mid-entry
+        let eof = rdb.len() - 9;
+        for len in 0..=eof {
+            let result = serialize(&rdb[..len]);
+            assert!(
+                result.is_err_and(|err| err.kind() == ErrorKind::InvalidData),
+                "cut at {}",
+                len
+            );
+        }
+        let mut bad = rdb.clone();
+        bad[..5].copy_from_slice(b"REDIX");
+        assert!(serialize(bad.as_slice()).is_err());
+        assert_eq!(serialize(rdb.as_slice()).unwrap()[&0].len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_touches_under_read_lock() {
+        let db = Arc::new(Database::new(Config::default()));
+        db.set("foo", "bar").await;
+        {
+            let db = db.db.read().await;
+            db.get("foo")
+                .unwrap()
+                .last_access
+                .store(0, Ordering::Relaxed);
+        }
+
+        // hold a read lock for the whole test: a GET that needed the write
+        // lock would block here instead of completing
+        let guard = db.db.read().await;
+        let gets: Vec<_> = (0..8)
+            .map(|_| {
+                let db = Arc::clone(&db);
+                tokio::spawn(async move { db.get("foo").await })
+            })
+            .collect();
+        for get in gets {
+            let value = tokio::time::timeout(Duration::from_secs(1), get)
+                .await
+                .expect("GET serialized behind the read lock")
+                .unwrap();
+            assert_eq!(value.unwrap().as_deref(), Some(&b"bar"[..]));
+        }
+
+        let last_access = guard
+            .get("foo")
+            .unwrap()
+            .last_access
+            .load(Ordering::Relaxed);
+        assert!(last_access > 0);
+    }
+
+    #[tokio::test]
+    async fn test_empty_key_and_value_round_trip() {
+        let db = Database::new(Config::default());
+        db.set("", "value").await;
+        db.set("key", "").await;
+        assert_eq!(db.get("").await.unwrap().as_deref(), Some(&b"value"[..]));
+        assert_eq!(db.get("key").await.unwrap().as_deref(), Some(&b""[..]));
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        let db = Database::new(Config::default());
+        db.set_with_expire("a", "1", 1).await;
+        db.set_with_expire("b", "2", 1).await;
+        db.set_with_expire("c", "3", 60_000).await;
+        db.set("d", "4").await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(db.purge_expired().await, 2);
+        assert_eq!(db.db.read().await.len(), 2);
+        assert_eq!(db.purge_expired().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_keys_with_concurrent_writes() {
+        let db = Arc::new(Database::new(Config::default()));
+        for i in 0..5000 {
+            db.set(&format!("key:{}", i), "v").await;
+        }
+        for i in 0..500 {
+            db.set_with_expire(&format!("expired:{}", i), "v", 1).await;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let writer = {
+            let db = Arc::clone(&db);
+            tokio::spawn(async move {
+                for i in 0..2000 {
+                    db.set(&format!("new:{}", i), "v").await;
+                }
+            })
+        };
+
+        let keys = tokio::time::timeout(Duration::from_secs(5), db.keys("*"))
+            .await
+            .expect("KEYS deadlocked");
+        writer.await.unwrap();
+
+        assert!(keys.iter().all(|k| !k.starts_with("expired:")));
+        assert_eq!(keys.iter().filter(|k| k.starts_with("key:")).count(), 5000);
+        let unique: HashSet<_> = keys.iter().collect();
+        assert_eq!(unique.len(), keys.len());
+        assert_eq!(db.db.read().await.len(), 7000);
+    }
+
+    #[tokio::test]
+    async fn test_memory_usage() {
+        let db = Database::new(Config::default());
+        db.set("foo", "bar").await;
+        db.set("big", &"x".repeat(1000)).await;
+        db.set_with_expire("gone", "bar", 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let small = db.memory_usage("foo", 5).await.unwrap();
+        let big = db.memory_usage("big", 5).await.unwrap();
+        assert!(small > "foo".len() + "bar".len());
+        assert!(big >= small + 997);
+        assert_eq!(db.memory_usage("missing", 5).await, None);
+        assert_eq!(db.memory_usage("gone", 5).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_memory_usage_samples() {
+        let db = Database::new(Config::default());
+        // five short items up front, then a thousand long ones
+        let mut list: VecDeque<_> = (0..5).map(|_| b"x".to_vec()).collect();
+        list.extend((0..1000).map(|_| vec![b'y'; 100]));
+        db.set("list", "").await;
+        db.db
+            .write()
+            .await
+            .update("list", |v| v.value = Value::List(list));
+
+        let exact = db.memory_usage("list", 0).await.unwrap();
+        let sampled = db.memory_usage("list", 5).await.unwrap();
+        assert!(exact > 1000 * 100);
+        assert!(sampled < exact / 2);
+        assert_eq!(db.memory_usage("list", 5000).await, Some(exact));
+        assert_eq!(exact, db.used_memory().await);
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_tracks_writes() {
+        async fn assert_in_step(db: &Database) {
+            let keys = db.db.read().await;
+            let summed: usize = keys.iter().map(|(k, v)| v.memory_usage(k)).sum();
+            assert_eq!(keys.used_memory, summed);
+            let mut indexed: Vec<_> = keys.by_hash.values().flatten().collect();
+            let mut all: Vec<_> = keys.keys().collect();
+            indexed.sort();
+            all.sort();
+            assert_eq!(indexed, all);
+        }
+
+        let db = Database::new(Config::default());
+        assert_eq!(db.used_memory().await, 0);
+        db.set("a", "bar").await;
+        db.set("b", &"x".repeat(1000)).await;
+        assert_in_step(&db).await;
+        let before = db.used_memory().await;
+
+        db.set("b", "short").await;
+        assert_in_step(&db).await;
+        assert!(db.used_memory().await < before);
+
+        db.append("a", &[b'y'; 100]).await.unwrap();
+        db.incr_by("n", 12345).await.unwrap();
+        db.rename("a", "c", false).await.unwrap();
+        assert_in_step(&db).await;
+
+        db.del(&["b".to_owned()]).await;
+        assert_in_step(&db).await;
+
+        db.set_with_expire("gone", "bar", 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(db.purge_expired().await, 1);
+        assert_in_step(&db).await;
+
+        db.flush().await;
+        assert_eq!(db.used_memory().await, 0);
+    }
+
+    #[test]
+    fn test_serialize_ignores_resizedb_count() {
+        let mut rdb = b"REDIS0011".to_vec();
+        // claims a single key, but three follow (one with a future expiry)
+        rdb.extend_from_slice(&[0xfe, 0x00, 0xfb, 0x01, 0x00]);
+        for key in [b"a", b"b"] {
+            rdb.extend_from_slice(&[0x00, 1]);
+            rdb.extend_from_slice(key);
+            rdb.extend_from_slice(&[1]);
+            rdb.extend_from_slice(b"v");
+        }
+        let expires_at = now_ms() + 60_000;
+        rdb.push(0xfc);
+        rdb.extend_from_slice(&expires_at.to_le_bytes());
+        rdb.extend_from_slice(&[0x00, 1]);
+        rdb.extend_from_slice(b"c");
+        rdb.extend_from_slice(&[0xc1, 0x39, 0x30]);
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+
+        let db = &serialize(rdb.as_slice()).unwrap()[&0];
+        assert_eq!(db.len(), 3);
+        assert_eq!(db["a"].value, Value::Str(b"v".to_vec()));
+        assert_eq!(db["b"].value, Value::Str(b"v".to_vec()));
+        assert_eq!(db["c"].value, Value::Str(b"12345".to_vec()));
+        assert!(db["c"].expires_at.is_some());
+    }
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+        let crc = 0xe9c6d914c4b8d9cau64.to_le_bytes();
+        assert!(verify_checksum(crc64(b"123456789"), Some(crc)).is_ok());
+        assert!(verify_checksum(crc64(b"123456780"), Some(crc)).is_err());
+        assert!(verify_checksum(crc64(b"123456780"), Some([0; 8])).is_ok());
+        assert!(verify_checksum(crc64(b"123456789"), None).is_err());
+    }
+
+    #[test]
+    fn test_serialize_stops_at_eof() {
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.extend_from_slice(&[0xfe, 0x00, 0xfb, 0x01, 0x00]);
+        rdb.extend_from_slice(&[0x00, 3]);
+        rdb.extend_from_slice(b"foo");
+        rdb.extend_from_slice(&[3]);
+        rdb.extend_from_slice(b"bar");
+        rdb.push(0xff);
+        let crc = crc64(&rdb);
+        rdb.extend_from_slice(&crc.to_le_bytes());
+        // trailing garbage that would parse as a string entry if read
+        rdb.extend_from_slice(&[0x00, 1, b'x', 1, b'y']);
+
+        let db = &serialize(rdb.as_slice()).unwrap()[&0];
+        assert_eq!(db.len(), 1);
+        assert_eq!(db["foo"].value, Value::Str(b"bar".to_vec()));
+
+        // a flipped value byte no longer matches the trailer
+        let at = rdb.windows(3).position(|w| w == b"bar").unwrap();
+        rdb[at] = b'c';
+        let err = serialize(rdb.as_slice()).err().unwrap();
+        assert!(err.to_string().contains("checksum mismatch"));
+    }
+
+    #[tokio::test]
+    async fn test_set_get_through_value_enum() {
+        let db = Database::new(Config::default());
+        db.set("foo", "bar").await;
+        assert_eq!(db.db.read().await["foo"].value, Value::Str(b"bar".to_vec()));
+        assert_eq!(db.get("foo").await.unwrap(), Some(b"bar".to_vec()));
+        assert_eq!(db.get("missing").await.unwrap(), None);
+
+        let list = Value::List(VecDeque::from([b"a".to_vec()]));
+        db.db
+            .write()
+            .await
+            .insert("list".to_string(), ExpiringValue::new(list, None));
+        assert!(matches!(db.get("list").await, Err(RespError::WrongType)));
+    }
+
+    #[test]
+    fn test_rdb_encode_round_trips_through_loader() {
+        for value in ["bar", "12345", "-7", "100000", "x".repeat(300).as_str()] {
+            let encoded = Value::Str(value.as_bytes().to_vec()).rdb_encode();
+            let mut rdb = RdbReader::new(encoded.as_slice());
+            assert_eq!(rdb.string().unwrap(), value.as_bytes());
+            // the whole encoding was consumed
+            assert_eq!(rdb.u8(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_debug_object_serialized_length() {
+        let db = Database::new(Config::default());
+        db.set("foo", "bar").await;
+        db.set("num", "12345").await;
+
+        let info = db.debug_object("foo").await.unwrap();
+        let payload = db.db.read().await["foo"].value.rdb_encode();
+        assert_eq!(info.serialized_length, payload.len());
+        assert_eq!(info.serialized_length, 4);
+        assert_eq!(info.encoding, "embstr");
+
+        let info = db.debug_object("num").await.unwrap();
+        assert_eq!(info.serialized_length, 3);
+        assert_eq!(info.encoding, "int");
+
+        assert!(db.debug_object("missing").await.is_none());
+    }
+
+    // the encoding is spliced into DEBUG OBJECT's simple-string reply, so it
+    // must be a single token for every type
+    #[tokio::test]
+    async fn test_debug_object_encodings_are_single_tokens() {
+        let db = Database::new(Config::default());
+        let item = || b"x".to_vec();
+        let values = [
+            Value::List([item()].into()),
+            Value::Hash(HashMap::from([(item(), item())])),
+            Value::Set(HashSet::from([item()])),
+            Value::ZSet(HashMap::from([(item(), 1.0)])),
+        ];
+        for value in values {
+            db.set("k", "v").await;
+            db.db.write().await.update("k", |v| v.value = value);
+            let info = db.debug_object("k").await.unwrap();
+            assert!(!info.encoding.is_empty());
+            assert!(info.encoding.bytes().all(|b| b.is_ascii_alphanumeric()));
+            assert!(info.serialized_length > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_owned_moves_value_into_store() {
+        let db = Database::new(Config::default());
+        let value = vec![b'x'; 1024 * 1024];
+        let ptr = value.as_ptr();
+        db.set_owned("big".to_string(), value, SetOptions::default())
+            .await
+            .unwrap();
+
+        match &db.db.read().await["big"].value {
+            Value::Str(stored) => {
+                assert_eq!(stored.as_ptr(), ptr);
+                assert_eq!(stored.len(), 1024 * 1024);
+            }
+            other => panic!("unexpected value {:?}", other),
+        }
+        assert_eq!(db.get("big").await.unwrap().unwrap().len(), 1024 * 1024);
+    }
+
+    #[test]
+    fn test_serialize_groups_keys_by_db() {
+        let mut rdb = b"REDIS0011".to_vec();
+        // no 0xFE before the first resizedb: these keys belong to db 0
+        rdb.extend_from_slice(&[0xfb, 0x01, 0x00]);
+        rdb.extend_from_slice(&[0x00, 1, b'a', 1, b'x']);
+        rdb.extend_from_slice(&[0xfe, 0x02, 0xfb, 0x01, 0x00]);
+        rdb.extend_from_slice(&[0x00, 1, b'b', 1, b'y']);
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+
+        let dbs = serialize(rdb.as_slice()).unwrap();
+        assert_eq!(dbs.len(), 2);
+        assert_eq!(dbs[&0].len(), 1);
+        assert_eq!(dbs[&0]["a"].value, Value::Str(b"x".to_vec()));
+        assert_eq!(dbs[&2].len(), 1);
+        assert_eq!(dbs[&2]["b"].value, Value::Str(b"y".to_vec()));
+    }
+
+    #[test]
+    fn test_relative_expiry_ignores_wall_clock_jumps() {
+        let set_at = Clock::now();
+        let value = ExpiringValue::new(
+            Value::Str(b"v".to_vec()),
+            Some(Expiry::Deadline(set_at.mono + Duration::from_millis(100))),
+        );
+
+        // the wall clock jumps an hour back, then an hour forward
+        let back = Duration::from_secs(3600);
+        let before = Clock {
+            wall: set_at.wall - back,
+            mono: set_at.mono + Duration::from_millis(50),
+        };
+        let after = Clock {
+            wall: set_at.wall - back,
+            mono: set_at.mono + Duration::from_millis(150),
+        };
+        let ahead = Clock {
+            wall: set_at.wall + back,
+            mono: set_at.mono + Duration::from_millis(50),
+        };
+        assert!(!value.is_expired(before));
+        assert!(value.is_expired(after));
+        assert!(!value.is_expired(ahead));
+
+        // absolute expiries still follow the wall clock
+        let absolute = ExpiringValue::new(
+            Value::Str(b"v".to_vec()),
+            Some(Expiry::At(set_at.wall + Duration::from_secs(60))),
+        );
+        assert!(!absolute.is_expired(before));
+        assert!(absolute.is_expired(ahead));
+    }
+
+    #[tokio::test]
+    async fn test_get_without_touch_keeps_idle_time() {
+        let db = Database::new(Config::default());
+        db.set("foo", "bar").await;
+        let stale = now_ms() - 5_000;
+        db.db.read().await["foo"]
+            .last_access
+            .store(stale, Ordering::Relaxed);
+
+        db.get_with("foo", false).await.unwrap();
+        assert_eq!(db.idle_time("foo").await, Some(5));
+        db.get_with("foo", true).await.unwrap();
+        assert_eq!(db.idle_time("foo").await, Some(0));
+        assert_eq!(db.idle_time("missing").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_del_counts_only_live_keys() {
+        let db = Database::new(Config::default());
+        db.set("a", "1").await;
+        db.set("b", "2").await;
+        db.set_with_expire("gone", "3", 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let keys = ["a", "b", "a", "gone", "missing"].map(String::from);
+        assert_eq!(db.del(&keys).await, 2);
+        assert!(db.db.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_exists_counts_duplicates_and_skips_expired() {
+        let db = Database::new(Config::default());
+        db.set("foo", "bar").await;
+        db.set_with_expire("gone", "x", 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(db.exists(&["missing".to_string()]).await, 0);
+        assert_eq!(db.exists(&["foo", "foo"].map(String::from)).await, 2);
+        assert_eq!(db.exists(&["gone".to_string()]).await, 0);
+        assert!(!db.db.read().await.contains_key("gone"));
+    }
+
+    #[tokio::test]
+    async fn test_incr_by() {
+        let db = Database::new(Config::default());
+        assert_eq!(db.incr_by("n", 1).await.unwrap(), 1);
+        assert_eq!(db.incr_by("n", -3).await.unwrap(), -2);
+        assert_eq!(db.get("n").await.unwrap(), Some(b"-2".to_vec()));
+
+        db.set("s", "abc").await;
+        assert!(matches!(
+            db.incr_by("s", 1).await,
+            Err(RespError::NotInteger)
+        ));
+        db.set("max", &i64::MAX.to_string()).await;
+        assert!(matches!(
+            db.incr_by("max", 1).await,
+            Err(RespError::Overflow)
+        ));
+        assert_eq!(
+            db.get("max").await.unwrap(),
+            Some(i64::MAX.to_string().into_bytes())
+        );
+        db.set("t", "1").await;
+        db.db
+            .write()
+            .await
+            .update("t", |v| v.value = Value::List(VecDeque::new()));
+        assert!(matches!(
+            db.incr_by("t", 1).await,
+            Err(RespError::WrongType)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_incr_by_keeps_ttl() {
+        let db = Database::new(Config::default());
+        db.set_with_expire("n", "10", 60_000).await;
+        let before = db.db.read().await["n"].expires_at;
+        assert_eq!(db.incr_by("n", 5).await.unwrap(), 15);
+        assert_eq!(db.db.read().await["n"].expires_at, before);
+        assert!(before.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_append() {
+        let db = Database::new(Config::default());
+        assert_eq!(db.append("new", b"hello").await.unwrap(), 5);
+        assert_eq!(db.get("new").await.unwrap(), Some(b"hello".to_vec()));
+
+        db.set_with_expire("foo", "ab", 60_000).await;
+        assert_eq!(db.append("foo", b"cd").await.unwrap(), 4);
+        assert_eq!(db.append("foo", b"efg").await.unwrap(), 7);
+        assert_eq!(db.get("foo").await.unwrap(), Some(b"abcdefg".to_vec()));
+        assert!(db.db.read().await["foo"].expires_at.is_some());
+    }
+
+    #[test]
+    fn test_ttl_rounds_up_to_seconds() {
+        assert_eq!(ttl_secs(1500), 2);
+        assert_eq!(ttl_secs(1), 1);
+        assert_eq!(ttl_secs(1000), 1);
+        assert_eq!(ttl_secs(1001), 2);
+        assert_eq!(ttl_secs(0), 0);
+
+        let now = Clock::now();
+        let expiry = Expiry::Deadline(now.mono + Duration::from_millis(1500));
+        assert_eq!(expiry.remaining_ms(now), 1500);
+        assert_eq!(ttl_secs(expiry.remaining_ms(now)), 2);
+        let expiry = Expiry::At(now.wall + Duration::from_millis(1500));
+        assert_eq!(expiry.remaining_ms(now), 1500);
+        let past = Expiry::At(now.wall - Duration::from_secs(1));
+        assert_eq!(past.remaining_ms(now), 0);
+    }
+
+    #[tokio::test]
+    async fn test_strlen() {
+        let db = Database::new(Config::default());
+        db.set("foo", "bar").await;
+        db.set("utf8", "héllo").await;
+        db.set_with_expire("gone", "x", 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(db.strlen("foo").await.unwrap(), 3);
+        assert_eq!(db.strlen("utf8").await.unwrap(), 6);
+        assert_eq!(db.strlen("missing").await.unwrap(), 0);
+        assert_eq!(db.strlen("gone").await.unwrap(), 0);
+
+        db.db
+            .write()
+            .await
+            .update("foo", |v| v.value = Value::List(VecDeque::new()));
+        assert!(matches!(db.strlen("foo").await, Err(RespError::WrongType)));
+    }
+
+    #[tokio::test]
+    async fn test_set_conditions() {
+        let db = Database::new(Config::default());
+        let set = |key: &str, value: &str, condition| {
+            let options = SetOptions {
+                condition,
+                ..SetOptions::default()
+            };
+            db.set_owned(key.to_owned(), value.into(), options)
+        };
+        assert!(!set("k", "v", SetCondition::Xx).await.unwrap().written);
+        assert_eq!(db.get("k").await.unwrap(), None);
+        assert!(set("k", "v", SetCondition::Nx).await.unwrap().written);
+        assert!(!set("k", "w", SetCondition::Nx).await.unwrap().written);
+        assert_eq!(db.get("k").await.unwrap(), Some(b"v".to_vec()));
+        assert!(set("k", "w", SetCondition::Xx).await.unwrap().written);
+        assert_eq!(db.get("k").await.unwrap(), Some(b"w".to_vec()));
+
+        // an expired key counts as absent
+        db.set_with_expire("gone", "x", 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(!set("gone", "y", SetCondition::Xx).await.unwrap().written);
+        assert!(set("gone", "y", SetCondition::Nx).await.unwrap().written);
+    }
+
+    #[tokio::test]
+    async fn test_bitfield_creates_key_only_on_write() {
+        let u8 = bitfield::FieldType {
+            signed: false,
+            bits: 8,
+        };
+        let db = Database::new(Config::default());
+        let get = [bitfield::Op::Get(u8, 0)];
+        assert_eq!(db.bitfield("k", &get).await.unwrap(), [Some(0)]);
+        assert!(!db.db.read().await.contains_key("k"));
+
+        let set = [bitfield::Op::Set(u8, 0, 65, bitfield::Overflow::Wrap)];
+        assert_eq!(db.bitfield("k", &set).await.unwrap(), [Some(0)]);
+        assert_eq!(db.get("k").await.unwrap(), Some(b"A".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_set_keep_ttl() {
+        let db = Database::new(Config::default());
+        db.set_with_expire("k", "v", 10_000).await;
+        let keep_ttl = SetOptions {
+            keep_ttl: true,
+            ..SetOptions::default()
+        };
+        db.set_owned("k".to_string(), b"w".to_vec(), keep_ttl)
+            .await
+            .unwrap();
+
+        {
+            let db = db.db.read().await;
+            assert_eq!(db["k"].value, Value::Str(b"w".to_vec()));
+            let left = db["k"].expires_at.unwrap().remaining_ms(Clock::now());
+            assert!(left > 9_000 && left <= 10_000);
+        }
+
+        // without KEEPTTL the overwrite clears the expiry, as before
+        db.set("k", "x").await;
+        assert_eq!(db.db.read().await["k"].expires_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_set_get_returns_old_value() {
+        let db = Database::new(Config::default());
+        let set_get = |value: &str, condition| {
+            let options = SetOptions {
+                get: true,
+                condition,
+                ..SetOptions::default()
+            };
+            db.set_owned("k".to_string(), value.into(), options)
+        };
+        let outcome = set_get("v", SetCondition::Always).await.unwrap();
+        assert_eq!((outcome.written, outcome.old), (true, None));
+        let outcome = set_get("w", SetCondition::Always).await.unwrap();
+        assert_eq!((outcome.written, outcome.old), (true, Some(b"v".to_vec())));
+        // NX still reports the value that blocked the write
+        let outcome = set_get("x", SetCondition::Nx).await.unwrap();
+        assert_eq!((outcome.written, outcome.old), (false, Some(b"w".to_vec())));
+        assert_eq!(db.get("k").await.unwrap(), Some(b"w".to_vec()));
+
+        db.db
+            .write()
+            .await
+            .update("k", |v| v.value = Value::List(VecDeque::new()));
+        let err = set_get("y", SetCondition::Always).await.unwrap_err();
+        assert!(matches!(err, RespError::WrongType));
+        assert!(matches!(db.db.read().await["k"].value, Value::List(_)));
+    }
+
+    #[tokio::test]
+    async fn test_mget() {
+        let db = Database::new(Config::default());
+        db.set("a", "1").await;
+        db.set_with_expire("gone", "2", 1).await;
+        db.set("list", "3").await;
+        db.db
+            .write()
+            .await
+            .update("list", |v| v.value = Value::List(VecDeque::new()));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let keys = ["a", "missing", "gone", "list", "a"].map(String::from);
+        let values = db.mget(&keys, true).await;
+        assert_eq!(values.len(), keys.len());
+        assert_eq!(
+            values,
+            [Some(b"1".to_vec()), None, None, None, Some(b"1".to_vec())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mset() {
+        let db = Database::new(Config::default());
+        db.set_with_expire("b", "old", 10_000).await;
+        let pairs = [("a", "1"), ("b", "2"), ("c", "3")];
+        db.mset(
+            pairs
+                .map(|(k, v)| (k.to_string(), v.as_bytes().to_vec()))
+                .to_vec(),
+        )
+        .await;
+        for (key, value) in pairs {
+            assert_eq!(db.get(key).await.unwrap(), Some(value.as_bytes().to_vec()));
+        }
+        assert_eq!(db.db.read().await["b"].expires_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_key_type() {
+        let db = Database::new(Config::default());
+        db.set("s", "v").await;
+        db.set("l", "v").await;
+        db.db
+            .write()
+            .await
+            .update("l", |v| v.value = Value::List(VecDeque::new()));
+        db.set_with_expire("gone", "v", 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(db.key_type("s").await, "string");
+        assert_eq!(db.key_type("l").await, "list");
+        assert_eq!(db.key_type("gone").await, "none");
+        assert_eq!(db.key_type("missing").await, "none");
+    }
+
+    fn rdb_entry(rdb: &mut Vec<u8>, kind: u8, key: &str) {
+        rdb.push(kind);
+        rdb_write_string(rdb, key.as_bytes());
+    }
+
+    // a listpack of short strings and 7-bit integers
+    fn listpack(entries: &[&[u8]]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for entry in entries {
+            match as_rdb_int(entry) {
+                Some(n @ 0..=127) => body.extend_from_slice(&[n as u8, 1]),
+                _ => {
+                    body.push(0x80 | entry.len() as u8);
+                    body.extend_from_slice(entry);
+                    body.push(1 + entry.len() as u8);
+                }
+            }
+        }
+        body.push(0xff);
+        let mut lp = ((body.len() + 6) as u32).to_le_bytes().to_vec();
+        lp.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        lp.extend(body);
+        lp
+    }
+
+    fn bytes(items: &[&str]) -> Vec<Vec<u8>> {
+        items.iter().map(|s| s.as_bytes().to_vec()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_container_types_round_trip_through_file() {
+        let [a, b] = [b"a".to_vec(), b"b".to_vec()];
+        let values = [
+            ("list", Value::List(bytes(&["x", "12", "x"]).into())),
+            ("set", Value::Set(bytes(&["m", "-3"]).into_iter().collect())),
+            ("hash", Value::Hash(HashMap::from([(a.clone(), b.clone())]))),
+            ("zset", Value::ZSet(HashMap::from([(a, 1.5), (b, -2.0)]))),
+        ];
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.extend_from_slice(&[0xfe, 0x00]);
+        for (key, value) in &values {
+            rdb_entry(&mut rdb, value.rdb_type(), key);
+            rdb.extend(value.rdb_encode());
+        }
+        rdb.push(0xff);
+        // a zero checksum is accepted as "not computed"
+        rdb.extend_from_slice(&[0; 8]);
+
+        let dir = std::env::temp_dir();
+        let dbfilename = format!("containers-{}.rdb", std::process::id());
+        std::fs::write(dir.join(&dbfilename), &rdb).unwrap();
+        let config = Config {
+            dir: Some(dir.to_string_lossy().into_owned()),
+            dbfilename: Some(dbfilename.clone()),
+            ..Config::default()
+        };
+        let db = Database::new(config);
+        std::fs::remove_file(dir.join(&dbfilename)).unwrap();
+
+        let loaded = db.db.read().await;
+        assert_eq!(loaded.len(), values.len());
+        for (key, value) in values {
+            assert_eq!(loaded[key].value, value);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loaded_expiry_is_live() {
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.extend_from_slice(&[0xfe, 0x00]);
+        rdb.push(0xfc);
+        rdb.extend_from_slice(&(now_ms() + 60_000).to_le_bytes());
+        rdb_entry(&mut rdb, 0, "k");
+        rdb_write_string(&mut rdb, b"v");
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+
+        let dir = std::env::temp_dir();
+        let dbfilename = format!("expiry-{}.rdb", std::process::id());
+        std::fs::write(dir.join(&dbfilename), &rdb).unwrap();
+        let db = Database::new(Config {
+            dir: Some(dir.to_string_lossy().into_owned()),
+            dbfilename: Some(dbfilename.clone()),
+            ..Config::default()
+        });
+        std::fs::remove_file(dir.join(&dbfilename)).unwrap();
+
+        match db.ttl("k").await {
+            TtlResult::Remaining(ms) => assert!(ms > 0 && ms <= 60_000),
+            _ => panic!("loaded key lost its expiry"),
+        }
+
+        let later = Expiry::after(Duration::from_secs(3600)).unwrap();
+        assert!(db.set_expiry("k", later, ExpireCondition::Always).await);
+        match db.ttl("k").await {
+            TtlResult::Remaining(ms) => assert!(ms > 60_000),
+            _ => panic!("EXPIRE dropped the expiry"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_compact_encodings() {
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb_entry(&mut rdb, 16, "hash");
+        rdb_write_string(&mut rdb, &listpack(&[b"f", b"v", b"n", b"7"]));
+        rdb_entry(&mut rdb, 17, "zset");
+        rdb_write_string(&mut rdb, &listpack(&[b"m", b"2.5", b"n", b"3"]));
+
+        // a 16-bit intset holding -2 and 300
+        rdb_entry(&mut rdb, 11, "intset");
+        let mut intset = vec![2, 0, 0, 0, 2, 0, 0, 0];
+        intset.extend_from_slice(&(-2i16).to_le_bytes());
+        intset.extend_from_slice(&300i16.to_le_bytes());
+        rdb_write_string(&mut rdb, &intset);
+
+        // a set listpack with a 13-bit and a 16-bit integer entry
+        rdb_entry(&mut rdb, 20, "lpset");
+        let mut lp = listpack(&[b"s"]);
+        lp.pop();
+        lp.extend_from_slice(&[0xdf, 0xff, 2]); // -1
+        lp.extend_from_slice(&[0xf1, 0x10, 0x27, 3]); // 10000
+        lp.push(0xff);
+        rdb_write_string(&mut rdb, &lp);
+
+        // quicklist 2: a packed node then a plain one
+        rdb_entry(&mut rdb, 18, "list");
+        rdb.extend_from_slice(&[2, 2]);
+        rdb_write_string(&mut rdb, &listpack(&[b"a", b"b"]));
+        rdb.push(1);
+        rdb_write_string(&mut rdb, b"big");
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+
+        let db = &serialize(rdb.as_slice()).unwrap()[&0];
+        let hash = HashMap::from([
+            (b"f".to_vec(), b"v".to_vec()),
+            (b"n".to_vec(), b"7".to_vec()),
+        ]);
+        assert_eq!(db["hash"].value, Value::Hash(hash));
+        let zset = HashMap::from([(b"m".to_vec(), 2.5), (b"n".to_vec(), 3.0)]);
+        assert_eq!(db["zset"].value, Value::ZSet(zset));
+        let set = |items| Value::Set(bytes(items).into_iter().collect());
+        assert_eq!(db["intset"].value, set(&["-2", "300"]));
+        assert_eq!(db["lpset"].value, set(&["s", "-1", "10000"]));
+        let list = Value::List(bytes(&["a", "b", "big"]).into());
+        assert_eq!(db["list"].value, list);
+    }
+
+    // a ziplist around already-encoded entries, each preceded by its prevlen
+    fn ziplist(entries: &[&[u8]]) -> Vec<u8> {
+        let mut body = Vec::new();
+        let mut prev = 0;
+        for entry in entries {
+            body.push(prev as u8);
+            body.extend_from_slice(entry);
+            prev = 1 + entry.len();
+        }
+        body.push(0xff);
+        let mut zl = ((body.len() + 10) as u32).to_le_bytes().to_vec();
+        zl.extend_from_slice(&0u32.to_le_bytes()); // tail offset, unused on load
+        zl.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zl.extend(body);
+        zl
+    }
+
+    #[test]
+    fn test_serialize_ziplist_quicklist() {
+        let mut rdb = b"REDIS0006".to_vec();
+        rdb_entry(&mut rdb, 14, "list");
+        rdb.push(2);
+        rdb_write_string(
+            &mut rdb,
+            &ziplist(&[
+                &[0x01, b'a'],
+                &[0xc0, 0xe8, 0x03], // int16 1000
+                &[0xf8],             // immediate 7
+                &[0xfe, 0xfb],       // int8 -5
+            ]),
+        );
+        rdb_write_string(
+            &mut rdb,
+            &ziplist(&[&[0x02, b'z', b'z'], &[0xf0, 0xff, 0xff, 0xff]]), // int24 -1
+        );
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+
+        let db = &serialize(rdb.as_slice()).unwrap()[&0];
+        let list = Value::List(bytes(&["a", "1000", "7", "-5", "zz", "-1"]).into());
+        assert_eq!(db["list"].value, list);
+    }
+
+    #[test]
+    fn test_serialize_reports_unsupported_types() {
+        for kind in [10, 13, 15] {
+            let mut rdb = b"REDIS0011".to_vec();
+            rdb_entry(&mut rdb, kind, "k");
+            rdb_write_string(&mut rdb, b"opaque");
+            rdb.push(0xff);
+            let err = serialize(rdb.as_slice()).err().unwrap();
+            assert!(err
+                .to_string()
+                .contains(&format!("unsupported RDB type {}", kind)));
+        }
+    }
+
+    #[test]
+    fn test_serialize_refuses_non_utf8_keys() {
+        let mut rdb = b"REDIS0011".to_vec();
+        for key in [b"\xff", b"\xfe"] {
+            rdb.push(0);
+            rdb_write_string(&mut rdb, key);
+            rdb_write_string(&mut rdb, b"v");
+        }
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+        let err = serialize(rdb.as_slice()).err().unwrap();
+        assert!(err.to_string().contains("not valid UTF-8"));
+    }
+
+    #[tokio::test]
+    async fn test_ttl() {
+        let db = Database::new(Config::default());
+        db.set_with_expire("k", "v", 5_000).await;
+        db.set("forever", "v").await;
+
+        let ttl = db.ttl("k").await;
+        assert!(matches!(ttl, TtlResult::Remaining(ms) if ms > 4_900 && ms <= 5_000));
+        assert!((4_900..=5_000).contains(&ttl.reply(true)));
+        assert_eq!(ttl.reply(false), 5);
+        assert_eq!(db.ttl("forever").await, TtlResult::NoExpiry);
+        assert_eq!(db.ttl("forever").await.reply(false), -1);
+        assert_eq!(db.ttl("missing").await, TtlResult::Missing);
+        assert_eq!(db.ttl("missing").await.reply(true), -2);
+    }
+
+    #[tokio::test]
+    async fn test_persist() {
+        let db = Database::new(Config::default());
+        db.set_with_expire("k", "v", 10_000).await;
+        db.set("forever", "v").await;
+        assert!(db.persist("k").await);
+        assert_eq!(db.ttl("k").await, TtlResult::NoExpiry);
+        assert!(!db.persist("k").await);
+        assert!(!db.persist("forever").await);
+        assert!(!db.persist("missing").await);
+    }
+
+    #[tokio::test]
+    async fn test_set_expiry() {
+        let db = Database::new(Config::default());
+        db.set("k", "v").await;
+        let expiry = Expiry::after(Duration::from_secs(10)).unwrap();
+        assert!(db.set_expiry("k", expiry, ExpireCondition::Always).await);
+        assert_eq!(db.ttl("k").await.reply(false), 10);
+        assert!(
+            !db.set_expiry("missing", expiry, ExpireCondition::Always)
+                .await
+        );
+
+        // already due: the key goes away but the call still counts
+        let past = Expiry::At(UNIX_EPOCH);
+        assert!(db.set_expiry("k", past, ExpireCondition::Always).await);
+        assert_eq!(db.ttl("k").await, TtlResult::Missing);
+        assert!(!db.db.read().await.contains_key("k"));
+    }
+
+    #[tokio::test]
+    async fn test_large_dump_loads_in_background() {
+        let mut rdb = b"REDIS0011".to_vec();
+        let value = vec![b'v'; 1024];
+        for i in 0..2048 {
+            rdb_entry(&mut rdb, 0, &format!("k{}", i));
+            rdb_write_string(&mut rdb, &value);
+        }
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+        assert!(rdb.len() as u64 > BACKGROUND_LOAD_BYTES);
+
+        let dir = std::env::temp_dir();
+        let dbfilename = format!("large-{}.rdb", std::process::id());
+        std::fs::write(dir.join(&dbfilename), &rdb).unwrap();
+        let db = Database::open(Config {
+            dir: Some(dir.to_string_lossy().into_owned()),
+            dbfilename: Some(dbfilename.clone()),
+            ..Config::default()
+        });
+        for _ in 0..500 {
+            if !db.is_loading() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        std::fs::remove_file(dir.join(&dbfilename)).unwrap();
+        assert!(!db.is_loading());
+        assert_eq!(db.db.read().await.len(), 2048);
+        assert_eq!(db.get("k2047").await.unwrap(), Some(value));
+    }
+
+    #[tokio::test]
+    async fn test_set_expiry_conditions() {
+        let db = Database::new(Config::default());
+        let secs = |n| Expiry::after(Duration::from_secs(n)).unwrap();
+        db.set("k", "v").await;
+
+        // no expiry yet: GT and XX refuse, LT and NX apply
+        assert!(!db.set_expiry("k", secs(100), ExpireCondition::Gt).await);
+        assert!(!db.set_expiry("k", secs(100), ExpireCondition::Xx).await);
+        assert!(!db.set_expiry("k", secs(100), ExpireCondition::XxLt).await);
+        assert!(db.set_expiry("k", secs(100), ExpireCondition::Nx).await);
+        assert!(!db.set_expiry("k", secs(200), ExpireCondition::Nx).await);
+
+        // GT won't shorten the TTL, LT won't lengthen it
+        assert!(!db.set_expiry("k", secs(50), ExpireCondition::Gt).await);
+        assert!(!db.set_expiry("k", secs(150), ExpireCondition::Lt).await);
+        assert_eq!(db.ttl("k").await.reply(false), 100);
+        assert!(db.set_expiry("k", secs(150), ExpireCondition::Gt).await);
+        assert!(db.set_expiry("k", secs(20), ExpireCondition::XxLt).await);
+        assert_eq!(db.ttl("k").await.reply(false), 20);
+
+        db.set("persistent", "v").await;
+        assert!(
+            db.set_expiry("persistent", secs(5), ExpireCondition::Lt)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let db = Database::new(Config::default());
+        db.set_with_expire("a", "1", 10_000).await;
+        db.set("b", "2").await;
+        assert!(db.rename("a", "b", false).await.unwrap());
+        assert_eq!(db.get("a").await.unwrap(), None);
+        assert_eq!(db.get("b").await.unwrap(), Some(b"1".to_vec()));
+        // the expiry moves with the value
+        assert_eq!(db.ttl("b").await.reply(false), 10);
+
+        db.set("c", "3").await;
+        assert!(!db.rename("b", "c", true).await.unwrap());
+        assert_eq!(db.get("c").await.unwrap(), Some(b"3".to_vec()));
+        assert!(db.rename("b", "d", true).await.unwrap());
+        assert!(!db.rename("d", "d", true).await.unwrap());
+        assert!(db.rename("d", "d", false).await.unwrap());
+
+        let err = db.rename("missing", "x", false).await.unwrap_err();
+        assert!(matches!(err, RespError::NoSuchKey));
+    }
+
+    #[tokio::test]
+    async fn test_keys_filters_by_pattern() {
+        let db = Database::new(Config::default());
+        for key in ["hello", "hallo", "hxllo", "hllo", "world"] {
+            db.set(key, "v").await;
+        }
+        let db = &db;
+        let keys = |pattern: &'static str| async move {
+            let mut keys = db.keys(pattern).await;
+            keys.sort();
+            keys
+        };
+        assert_eq!(keys("h?llo").await, ["hallo", "hello", "hxllo"]);
+        assert_eq!(keys("h[ae]llo").await, ["hallo", "hello"]);
+        assert_eq!(keys("h*llo").await, ["hallo", "hello", "hllo", "hxllo"]);
+        assert_eq!(keys("world").await, ["world"]);
+        assert_eq!(keys("*").await.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_scan_pages_cover_every_key_once() {
+        let db = Database::new(Config::default());
+        for i in 0..25 {
+            db.set(&format!("key:{}", i), "v").await;
+        }
+        db.set_with_expire("expired", "v", 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let (mut cursor, mut seen, mut pages) = (0, Vec::new(), 0);
+        loop {
+            let (next, keys) = db.scan(cursor, 7, None, None).await;
+            assert!(keys.len() <= 7);
+            seen.extend(keys);
+            pages += 1;
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(pages, 4);
+        let total = seen.len();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), total);
+        assert_eq!(total, 25);
+        assert!(!seen.contains(&"expired".to_string()));
+
+        let (next, keys) = db.scan(0, 100, Some("key:1*"), Some("string")).await;
+        assert_eq!(next, 0);
+        assert_eq!(keys.len(), 11);
+        let (_, keys) = db.scan(0, 100, None, Some("list")).await;
+        assert!(keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scan_survives_deletes_and_inserts() {
+        let db = Database::new(Config::default());
+        for i in 0..100 {
+            db.set(&format!("key:{}", i), "v").await;
+        }
+
+        let (mut cursor, mut seen, mut round) = (0, Vec::new(), 0);
+        loop {
+            let (next, keys) = db.scan(cursor, 10, None, None).await;
+            seen.extend(keys);
+            // between pages, drop a key already returned and add new ones
+            // that may land on either side of the cursor
+            let done = seen.iter().filter(|key| key.starts_with("key:")).nth(round);
+            if let Some(done) = done.cloned() {
+                db.del(&[done]).await;
+            }
+            for i in 0..5 {
+                db.set(&format!("new:{}:{}", round, i), "v").await;
+            }
+            round += 1;
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        let total = seen.len();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), total, "a key was returned twice");
+        for i in 0..100 {
+            assert!(
+                seen.contains(&format!("key:{}", i)),
+                "key:{} was skipped",
+                i
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_misses_no_key_under_concurrent_inserts() {
+        let db = Arc::new(Database::new(Config::default()));
+        for i in 0..1000 {
+            db.set(&format!("key:{}", i), "v").await;
+        }
+        let writer = {
+            let db = Arc::clone(&db);
+            tokio::spawn(async move {
+                for i in 0..2000 {
+                    db.set(&format!("new:{}", i), "v").await;
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let (mut cursor, mut seen) = (0, HashSet::new());
+        loop {
+            let (next, keys) = db.scan(cursor, 25, None, None).await;
+            seen.extend(keys);
+            tokio::task::yield_now().await;
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        writer.await.unwrap();
+        assert!((0..1000).all(|i| seen.contains(&format!("key:{}", i))));
+    }
+
+    #[tokio::test]
+    async fn test_dbsize_skips_and_purges_expired() {
+        let db = Database::new(Config::default());
+        db.set("a", "1").await;
+        db.set("b", "2").await;
+        db.set_with_expire("c", "3", 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(db.dbsize().await, 2);
+        assert!(!db.db.read().await.contains_key("c"));
+    }
+}